@@ -0,0 +1,69 @@
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "assettosync-client",
+    about = "Client for AssettoSync - app for downloading and installing mods to Assetto Corsa game",
+    after_help = "Without a subcommand the graphical interface is started."
+)]
+pub struct Args {
+    /// Profile to use, created when it doesn't exist yet (default: the last used one)
+    #[structopt(long, env = "ASSETTO_SYNC_PROFILE")]
+    pub profile: Option<String>,
+
+    /// Server base URL, overrides server_url from the config file
+    #[structopt(long, env = "ASSETTO_SYNC_SERVER_URL")]
+    pub server_url: Option<String>,
+
+    /// API token sent instead of logging in with a password, overrides the stored token of the profile
+    #[structopt(long, env = "ASSETTO_SYNC_API_TOKEN", hide_env_values = true)]
+    pub api_token: Option<String>,
+
+    /// Assetto Corsa directory (containing acs.exe), stored in the config file when valid
+    #[structopt(long)]
+    pub assetto_path: Option<String>,
+
+    /// Print machine-readable JSON on stdout (command line mode only)
+    #[structopt(long, global = true)]
+    pub json: bool,
+
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum Command {
+    /// Log in to the server and store the credentials in the config file
+    Login {
+        #[structopt(long)]
+        login: Option<String>,
+        #[structopt(long, env = "ASSETTO_SYNC_PASSWORD", hide_env_values = true)]
+        password: Option<String>,
+    },
+    /// List mods available on the server
+    List,
+    /// Install mods selected by MD5 checksum or filename
+    Install {
+        #[structopt(required = true)]
+        mods: Vec<String>,
+        /// Download and unpack the mods, show where files would go and ask before installing
+        #[structopt(long)]
+        dry_run: bool,
+    },
+    /// Remove files of installed mods selected by MD5 checksum or filename
+    Uninstall {
+        #[structopt(required = true)]
+        mods: Vec<String>,
+    },
+    /// Undo an install session using the backup of replaced files, lists backups without an argument
+    Restore { session: Option<String> },
+    /// Install mods which are not installed yet
+    Sync {
+        /// Install every mod available on the server
+        #[structopt(long)]
+        all: bool,
+        /// Download and unpack the mods, show where files would go and ask before installing
+        #[structopt(long)]
+        dry_run: bool,
+    },
+}
@@ -0,0 +1,119 @@
+use std::{future::Future, pin::Pin, sync::Mutex};
+
+use reqwest::{
+    header::{HeaderMap, HeaderValue, AUTHORIZATION},
+    Client,
+};
+
+use crate::endpoints::Endpoints;
+use crate::error::{Error, Result};
+use crate::server::{self, LoginData, StoredSession};
+
+pub type AuthFuture<'a> = Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>>;
+
+// How requests to the server are authorized, shared by the mod list and the
+// downloads through Session
+pub trait Authenticator: Send + Sync {
+    fn get_client(&self) -> Client;
+    // None when there is nothing worth keeping for the next run
+    fn get_stored_session(&self) -> Option<StoredSession>;
+    // called when the server rejected a request, Session runs one at a time
+    fn reauthenticate(&self) -> AuthFuture<'_>;
+}
+
+// A login with the password, the cookies it got are replaced by a new login when
+// the session expires
+pub struct PasswordAuthenticator {
+    client: Mutex<Client>,
+    stored_session: Mutex<StoredSession>,
+    endpoints: Endpoints,
+    login_data: LoginData,
+}
+
+impl PasswordAuthenticator {
+    pub fn new(
+        client: Client,
+        stored_session: StoredSession,
+        endpoints: Endpoints,
+        login_data: LoginData,
+    ) -> PasswordAuthenticator {
+        PasswordAuthenticator {
+            client: Mutex::new(client),
+            stored_session: Mutex::new(stored_session),
+            endpoints,
+            login_data,
+        }
+    }
+}
+
+impl Authenticator for PasswordAuthenticator {
+    fn get_client(&self) -> Client {
+        self.client.lock().unwrap().clone()
+    }
+
+    fn get_stored_session(&self) -> Option<StoredSession> {
+        Some(self.stored_session.lock().unwrap().clone())
+    }
+
+    fn reauthenticate(&self) -> AuthFuture<'_> {
+        Box::pin(async move {
+            eprintln!(
+                "Session expired, logging in again as {}",
+                self.login_data.login
+            );
+            let (client, stored_session) =
+                server::login_client(&self.endpoints, &self.login_data).await?;
+            *self.client.lock().unwrap() = client;
+            *self.stored_session.lock().unwrap() = stored_session;
+            Ok(())
+        })
+    }
+}
+
+// An API token sent as a bearer header with every request, for machines which
+// sync without a user password. There is no session, a rejected token stays rejected
+pub struct TokenAuthenticator {
+    client: Client,
+}
+
+impl TokenAuthenticator {
+    pub fn new(token: &str) -> Result<TokenAuthenticator> {
+        let header = HeaderValue::from_str(&format!("Bearer {}", token.trim()));
+        if header.is_err() {
+            return Err(Error::Config(
+                "API token contains invalid characters".to_string(),
+            ));
+        }
+        let mut header = header.unwrap();
+        header.set_sensitive(true);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, header);
+        let client = server::client_builder().default_headers(headers).build()?;
+        Ok(TokenAuthenticator { client })
+    }
+}
+
+impl Authenticator for TokenAuthenticator {
+    fn get_client(&self) -> Client {
+        self.client.clone()
+    }
+
+    fn get_stored_session(&self) -> Option<StoredSession> {
+        None
+    }
+
+    fn reauthenticate(&self) -> AuthFuture<'_> {
+        Box::pin(async { Err(Error::Auth("API token rejected by the server".to_string())) })
+    }
+}
+
+// the API token from the command line or ASSETTO_SYNC_API_TOKEN overrides the
+// one in the config file, None when neither is set
+pub fn get_api_token(args_token: &Option<String>, config_token: &str) -> Option<String> {
+    let token = args_token.clone().unwrap_or(config_token.to_string());
+    match token.trim().is_empty() {
+        true => None,
+        false => Some(token),
+    }
+}
@@ -0,0 +1,411 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{ConfigObject, ConfigTrait};
+use crate::error::{Error, Result};
+use crate::manifest::{ManifestObject, ManifestTrait};
+use crate::uninstall;
+
+const SESSION_FILE_NAME: &str = "session.json";
+const FILES_DIR_NAME: &str = "files";
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BackupFile {
+    pub path: String,
+    pub backed_up: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BackupMod {
+    pub checksum_md5: String,
+    pub filename: String,
+}
+
+// One session per install run, it records the state of every file touched by the
+// run from before the first mod was installed, so restoring undoes the whole run
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BackupSession {
+    pub id: String,
+    pub created: u64,
+    // the game directory the files were installed to
+    #[serde(default)]
+    pub assetto_path: String,
+    pub mods: Vec<BackupMod>,
+    pub files: Vec<BackupFile>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+pub struct RestoreResult {
+    pub restored_files: Vec<String>,
+    pub removed_files: Vec<String>,
+    pub mods: Vec<BackupMod>,
+}
+
+pub fn get_backup_root() -> PathBuf {
+    dirs::data_dir()
+        .unwrap_or(std::env::temp_dir())
+        .join("assetto_sync_client")
+        .join("backups")
+}
+
+// every profile has its own backups, like its own manifest
+pub fn get_backup_dir(profile: &str) -> PathBuf {
+    get_backup_root().join(profile)
+}
+
+impl BackupSession {
+    pub fn new(backup_dir: &Path, assetto_path: &str) -> BackupSession {
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+        let mut id = created.to_string();
+        let mut suffix = 1;
+        while backup_dir.join(&id).exists() {
+            id = format!("{}-{}", created, suffix);
+            suffix += 1;
+        }
+
+        BackupSession {
+            path: backup_dir.join(&id),
+            id,
+            created,
+            assetto_path: assetto_path.to_string(),
+            mods: vec![],
+            files: vec![],
+        }
+    }
+
+    pub fn load(backup_dir: &Path, id: &str) -> Result<BackupSession> {
+        let path = backup_dir.join(id);
+        let content = std::fs::read_to_string(path.join(SESSION_FILE_NAME));
+        if let Err(error) = content {
+            return Err(Error::Filesystem(format!(
+                "Backup {} cannot be read: {}",
+                id, error
+            )));
+        }
+        let session = serde_json::from_str::<BackupSession>(&content.unwrap());
+        if let Err(error) = session {
+            return Err(Error::Filesystem(format!(
+                "Backup {} is corrupt: {}",
+                id, error
+            )));
+        }
+        let mut session = session.unwrap();
+        session.path = path;
+        Ok(session)
+    }
+
+    pub fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    // backup_path is None when the file did not exist before, restoring removes it then
+    pub fn add_file(&mut self, relative_path: &str, backup_path: Option<&Path>) -> Result<()> {
+        if self.files.iter().any(|file| file.path == relative_path) {
+            return Ok(());
+        }
+
+        if let Some(backup_path) = backup_path {
+            let target_path = self.path.join(FILES_DIR_NAME).join(relative_path);
+            let result = std::fs::create_dir_all(target_path.parent().unwrap())
+                .and_then(|_| std::fs::copy(backup_path, &target_path));
+            if let Err(error) = result {
+                return Err(Error::Filesystem(format!(
+                    "cannot back up {}: {}",
+                    relative_path, error
+                )));
+            }
+        }
+        self.files.push(BackupFile {
+            path: relative_path.to_string(),
+            backed_up: backup_path.is_some(),
+        });
+        Ok(())
+    }
+
+    pub fn add_mod(&mut self, checksum_md5: &str, filename: &str) {
+        self.mods.push(BackupMod {
+            checksum_md5: checksum_md5.to_string(),
+            filename: filename.to_string(),
+        });
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let json = serde_json::to_string_pretty(self).unwrap();
+        let result = std::fs::create_dir_all(&self.path)
+            .and_then(|_| std::fs::write(self.path.join(SESSION_FILE_NAME), json));
+        if let Err(error) = result {
+            return Err(Error::Filesystem(format!(
+                "cannot write backup {}: {}",
+                self.id, error
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn describe(&self) -> String {
+        let filenames: Vec<&str> = self
+            .mods
+            .iter()
+            .map(|entry| entry.filename.as_str())
+            .collect();
+        format!("{}: {}", self.id, filenames.join(", "))
+    }
+}
+
+// newest session first
+pub fn list_sessions(backup_dir: &Path) -> Vec<BackupSession> {
+    let mut sessions = vec![];
+    if let Ok(entries) = std::fs::read_dir(backup_dir) {
+        for entry in entries.flatten() {
+            let id = entry.file_name().to_string_lossy().to_string();
+            match BackupSession::load(backup_dir, &id) {
+                Ok(session) => sessions.push(session),
+                Err(error) => eprintln!("{}", error),
+            }
+        }
+    }
+    sessions.sort_by(|a, b| b.created.cmp(&a.created).then(b.id.cmp(&a.id)));
+    sessions
+}
+
+// Older versions kept the sessions of their only game directory right in the backup
+// root, they are moved to the profile which took over the settings
+pub fn move_legacy_sessions(backup_root: &Path, profile: &str, assetto_path: &str) {
+    let entries = match std::fs::read_dir(backup_root) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    let backup_dir = backup_root.join(profile);
+    for entry in entries.flatten() {
+        if !entry.path().join(SESSION_FILE_NAME).exists() {
+            continue;
+        }
+        let id = entry.file_name().to_string_lossy().to_string();
+        let result = std::fs::create_dir_all(&backup_dir)
+            .and_then(|_| std::fs::rename(entry.path(), backup_dir.join(&id)));
+        if let Err(error) = result {
+            eprintln!("Cannot move backup {}: {}", id, error);
+            continue;
+        }
+        let result = BackupSession::load(&backup_dir, &id).and_then(|mut session| {
+            session.assetto_path = assetto_path.to_string();
+            session.save()
+        });
+        if let Err(error) = result {
+            eprintln!("{}", error);
+        }
+    }
+}
+
+pub fn restore_session(
+    assetto_path: &Path,
+    backup_dir: &Path,
+    id: &str,
+    config: &mut ConfigObject,
+    manifest: &mut ManifestObject,
+) -> Result<RestoreResult> {
+    let session = BackupSession::load(backup_dir, id)?;
+    if Path::new(&session.assetto_path) != assetto_path {
+        return Err(Error::Config(format!(
+            "Backup {} was made for {}, not for {}",
+            id,
+            session.assetto_path,
+            assetto_path.display()
+        )));
+    }
+    // a newer session may have replaced the same files again, restoring an older one
+    // first would leave its files and mods half undone
+    if let Some(newest) = list_sessions(backup_dir).first() {
+        if newest.id != session.id {
+            return Err(Error::Config(format!(
+                "Backup {} is older than backup {}, the newer backups have to be restored first",
+                id, newest.id
+            )));
+        }
+    }
+    let mut result = RestoreResult {
+        restored_files: vec![],
+        removed_files: vec![],
+        mods: session.mods.clone(),
+    };
+
+    for file in session.files.iter().rev() {
+        let relative_path = Path::new(&file.path);
+        let target_path = assetto_path.join(relative_path);
+        if file.backed_up {
+            let backup_path = session.path.join(FILES_DIR_NAME).join(relative_path);
+            let copy_result = std::fs::create_dir_all(target_path.parent().unwrap())
+                .and_then(|_| std::fs::copy(&backup_path, &target_path));
+            if let Err(error) = copy_result {
+                return Err(Error::Filesystem(format!(
+                    "Backup {}, cannot restore {}: {}",
+                    id, file.path, error
+                )));
+            }
+            result.restored_files.push(file.path.clone());
+            continue;
+        }
+
+        if let Err(error) = std::fs::remove_file(&target_path) {
+            if error.kind() != std::io::ErrorKind::NotFound {
+                return Err(Error::Filesystem(format!(
+                    "Backup {}, cannot remove {}: {}",
+                    id, file.path, error
+                )));
+            }
+        }
+        uninstall::remove_empty_parent_dirs(assetto_path, relative_path);
+        result.removed_files.push(file.path.clone());
+    }
+
+    for entry in session.mods.iter() {
+        manifest.remove_mod(&entry.checksum_md5)?;
+        config.remove_installed_mod(&entry.checksum_md5);
+    }
+
+    if let Err(error) = std::fs::remove_dir_all(&session.path) {
+        eprintln!("Backup {} restored, but cannot be removed: {}", id, error);
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secret_store::EncryptedFileStore;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_restore_session() {
+        let temp_dir = TempDir::new("backup_test").unwrap();
+        let assetto_path = temp_dir.path().join("assetto");
+        let backup_dir = temp_dir.path().join("backups");
+        let original_path = temp_dir.path().join("original");
+        std::fs::write(&original_path, "old").unwrap();
+
+        std::fs::create_dir_all(assetto_path.join("content/cars/a")).unwrap();
+        std::fs::create_dir_all(assetto_path.join("content/cars/b/skins")).unwrap();
+        std::fs::write(assetto_path.join("content/cars/a/data.acd"), "new").unwrap();
+        std::fs::write(assetto_path.join("content/cars/b/skins/data.acd"), "new").unwrap();
+
+        let md5 = "abcdef".to_string();
+        let mut session = BackupSession::new(&backup_dir, assetto_path.to_str().unwrap());
+        session
+            .add_file("content/cars/a/data.acd", Some(&original_path))
+            .unwrap();
+        session
+            .add_file("content/cars/b/skins/data.acd", None)
+            .unwrap();
+        session.add_mod(&md5, "mod.zip");
+        session.save().unwrap();
+        assert_eq!(list_sessions(&backup_dir).len(), 1);
+
+        let secrets = EncryptedFileStore::new(
+            &temp_dir.path().join("secrets"),
+            &temp_dir.path().join("key"),
+        );
+        let mut config = ConfigObject::open(
+            temp_dir.path().join("config.json").to_str().unwrap(),
+            Box::new(secrets),
+            &temp_dir.path().join("backups"),
+        );
+        config.add_installed_mod(&md5);
+        let mut manifest =
+            ManifestObject::new(temp_dir.path().join("manifest.json").to_str().unwrap());
+
+        // the backup doesn't belong to another game directory
+        let result = restore_session(
+            &temp_dir.path().join("other"),
+            &backup_dir,
+            &session.id,
+            &mut config,
+            &mut manifest,
+        );
+        assert!(matches!(result, Err(Error::Config(_))));
+        assert!(config.is_mod_installed(&md5));
+
+        let result = restore_session(
+            &assetto_path,
+            &backup_dir,
+            &session.id,
+            &mut config,
+            &mut manifest,
+        )
+        .unwrap();
+
+        assert_eq!(result.restored_files, vec!["content/cars/a/data.acd"]);
+        assert_eq!(result.removed_files, vec!["content/cars/b/skins/data.acd"]);
+        assert_eq!(
+            std::fs::read_to_string(assetto_path.join("content/cars/a/data.acd")).unwrap(),
+            "old"
+        );
+        assert!(!assetto_path.join("content/cars/b").exists());
+        assert!(!config.is_mod_installed(&md5));
+        assert_eq!(list_sessions(&backup_dir).len(), 0);
+    }
+
+    #[test]
+    fn test_restore_newest_session_first() {
+        let temp_dir = TempDir::new("backup_test").unwrap();
+        let assetto_path = temp_dir.path().join("assetto");
+        let backup_dir = temp_dir.path().join("backups");
+        let data_path = assetto_path.join("content/cars/a/data.acd");
+        std::fs::create_dir_all(data_path.parent().unwrap()).unwrap();
+
+        // both sessions replaced the same file, the second one on top of the first
+        let mut sessions = vec![];
+        for (content, md5) in [("original", "abc"), ("first", "def")] {
+            std::fs::write(&data_path, content).unwrap();
+            let mut session = BackupSession::new(&backup_dir, assetto_path.to_str().unwrap());
+            session
+                .add_file("content/cars/a/data.acd", Some(&data_path))
+                .unwrap();
+            session.add_mod(md5, "mod.zip");
+            session.save().unwrap();
+            sessions.push(session);
+        }
+        std::fs::write(&data_path, "second").unwrap();
+
+        let secrets = EncryptedFileStore::new(
+            &temp_dir.path().join("secrets"),
+            &temp_dir.path().join("key"),
+        );
+        let mut config = ConfigObject::open(
+            temp_dir.path().join("config.json").to_str().unwrap(),
+            Box::new(secrets),
+            &backup_dir,
+        );
+        config.add_installed_mod(&"abc".to_string());
+        config.add_installed_mod(&"def".to_string());
+        let mut manifest =
+            ManifestObject::new(temp_dir.path().join("manifest.json").to_str().unwrap());
+
+        let mut restore = |session: &BackupSession| {
+            restore_session(
+                &assetto_path,
+                &backup_dir,
+                &session.id,
+                &mut config,
+                &mut manifest,
+            )
+        };
+        assert!(matches!(restore(&sessions[0]), Err(Error::Config(_))));
+        assert_eq!(std::fs::read_to_string(&data_path).unwrap(), "second");
+        assert_eq!(list_sessions(&backup_dir).len(), 2);
+
+        restore(&sessions[1]).unwrap();
+        assert_eq!(std::fs::read_to_string(&data_path).unwrap(), "first");
+        restore(&sessions[0]).unwrap();
+        assert_eq!(std::fs::read_to_string(&data_path).unwrap(), "original");
+        assert!(!config.is_mod_installed(&"abc".to_string()));
+        assert!(!config.is_mod_installed(&"def".to_string()));
+    }
+}
@@ -0,0 +1,851 @@
+use std::{path::Path, time::Duration};
+
+use serde::Serialize;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+use crate::args::{Args, Command};
+use crate::auth;
+use crate::backup;
+use crate::common;
+use crate::config::{ConfigObject, ConfigTrait};
+use crate::endpoints::Endpoints;
+use crate::error::{Error, ModError};
+use crate::install_thread::{
+    InstallEvent, InstallProgress, InstallThread, InstallThreadTrait, ModOutcome,
+};
+use crate::manifest::{ManifestObject, ManifestTrait};
+use crate::retry;
+use crate::server::{self, LoginData, Session};
+use crate::uninstall;
+use crate::JsonModTemplate;
+
+pub const EXIT_SUCCESS: i32 = 0;
+pub const EXIT_USAGE: i32 = 1;
+pub const EXIT_CONFIG: i32 = 2;
+pub const EXIT_AUTH: i32 = 3;
+pub const EXIT_NETWORK: i32 = 4;
+pub const EXIT_INSTALL_FAILED: i32 = 5;
+pub const EXIT_CANCELLED: i32 = 6;
+
+struct CliError {
+    exit_code: i32,
+    message: String,
+}
+
+impl CliError {
+    fn new(exit_code: i32, message: String) -> CliError {
+        CliError { exit_code, message }
+    }
+
+    fn from_error(error: Error) -> CliError {
+        let exit_code = match error {
+            Error::Auth(_) | Error::SessionExpired => EXIT_AUTH,
+            Error::Network(_) | Error::Http { .. } => EXIT_NETWORK,
+            Error::Config(_) => EXIT_CONFIG,
+            _ => EXIT_INSTALL_FAILED,
+        };
+        CliError::new(exit_code, error.to_string())
+    }
+}
+
+#[derive(Serialize)]
+struct JsonError<'a> {
+    error: &'a str,
+    exit_code: i32,
+}
+
+#[derive(Serialize)]
+struct JsonLogin<'a> {
+    login: &'a str,
+}
+
+#[derive(Serialize)]
+struct JsonModListEntry<'a> {
+    checksum_md5: &'a str,
+    filename: &'a str,
+    size_in_bytes: u64,
+    installed: bool,
+}
+
+#[derive(Serialize)]
+struct JsonUninstallSummary<'a> {
+    uninstalled: Vec<String>,
+    errors: Vec<JsonModError<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonModError<'a> {
+    checksum_md5: &'a str,
+    filename: &'a str,
+    kind: &'a str,
+    message: String,
+    attempts: u32,
+}
+
+#[derive(Serialize)]
+struct JsonInstallSummary<'a> {
+    installed: Vec<String>,
+    skipped: Vec<String>,
+    failed: Vec<String>,
+    unfinished: Vec<String>,
+    errors: Vec<JsonModError<'a>>,
+    backup_session: Option<String>,
+    cancelled: bool,
+}
+
+#[derive(Serialize)]
+struct JsonPlannedTask<'a> {
+    source_path: &'a str,
+    target_path: &'a str,
+}
+
+#[derive(Serialize)]
+struct JsonInstallPlan<'a> {
+    checksum_md5: &'a str,
+    filename: &'a str,
+    tasks: Vec<JsonPlannedTask<'a>>,
+    file_count: usize,
+    overwritten_files: Vec<&'a str>,
+}
+
+#[derive(Serialize)]
+struct JsonDryRunSummary<'a> {
+    plans: Vec<JsonInstallPlan<'a>>,
+    skipped: Vec<String>,
+    errors: Vec<JsonModError<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonBackupSession<'a> {
+    id: &'a str,
+    created: u64,
+    mods: Vec<&'a str>,
+}
+
+#[derive(Serialize)]
+struct JsonRestoreSummary<'a> {
+    session: &'a str,
+    mods: Vec<String>,
+    restored_files: Vec<String>,
+    removed_files: Vec<String>,
+}
+
+fn to_json_errors<'a>(error_list: &'a [ModError]) -> Vec<JsonModError<'a>> {
+    error_list
+        .iter()
+        .map(|error| JsonModError {
+            checksum_md5: &error.checksum_md5,
+            filename: &error.filename,
+            kind: error.error.kind(),
+            message: error.error.to_string(),
+            attempts: error.attempts,
+        })
+        .collect()
+}
+
+pub fn report_error(json: bool, exit_code: i32, message: &str) -> i32 {
+    if json {
+        let error = JsonError {
+            error: message,
+            exit_code,
+        };
+        println!("{}", serde_json::to_string(&error).unwrap());
+    } else {
+        eprintln!("Error: {}", message);
+    }
+    exit_code
+}
+
+pub async fn run(
+    command: &Command,
+    args: &Args,
+    config: &mut ConfigObject,
+    manifest: &mut ManifestObject,
+    endpoints: &Endpoints,
+) -> i32 {
+    let result = match command {
+        Command::Login { login, password } => {
+            login_command(login, password, args.json, config, endpoints).await
+        }
+        Command::List => list_command(args, config, endpoints).await,
+        Command::Install { mods, dry_run } => {
+            install_command(mods, *dry_run, args, config, manifest, endpoints).await
+        }
+        Command::Sync { all, dry_run } => {
+            sync_command(*all, *dry_run, args, config, manifest, endpoints).await
+        }
+        Command::Uninstall { mods } => uninstall_command(mods, args, config, manifest),
+        Command::Restore { session } => restore_command(session, args, config, manifest),
+    };
+
+    match result {
+        Ok(exit_code) => exit_code,
+        Err(error) => report_error(args.json, error.exit_code, &error.message),
+    }
+}
+
+async fn login_command(
+    login: &Option<String>,
+    password: &Option<String>,
+    json: bool,
+    config: &mut ConfigObject,
+    endpoints: &Endpoints,
+) -> Result<i32, CliError> {
+    let login_data = LoginData {
+        login: login.clone().unwrap_or(config.get_profile().login.clone()),
+        password: password.clone().unwrap_or(config.get_password()),
+    };
+    if login_data.login.is_empty() {
+        return Err(CliError::new(
+            EXIT_USAGE,
+            "No login provided, use --login".to_string(),
+        ));
+    }
+
+    let session = server::login(endpoints, &login_data).await;
+    if let Err(error) = session {
+        return Err(CliError::from_error(error));
+    }
+    let session = session.unwrap();
+
+    if json {
+        let output = JsonLogin {
+            login: &login_data.login,
+        };
+        println!("{}", serde_json::to_string(&output).unwrap());
+    } else {
+        println!("Logged in as {}", login_data.login);
+    }
+
+    config.set_login(login_data.login);
+    config.set_password(login_data.password);
+    save_session(config, &session);
+    Ok(EXIT_SUCCESS)
+}
+
+async fn list_command(
+    args: &Args,
+    config: &mut ConfigObject,
+    endpoints: &Endpoints,
+) -> Result<i32, CliError> {
+    let session = authenticate(args, config, endpoints).await?;
+    let mod_list = fetch_mod_list(&session, config).await?;
+
+    if args.json {
+        let entries: Vec<JsonModListEntry> = mod_list
+            .iter()
+            .map(|entry| JsonModListEntry {
+                checksum_md5: &entry.checksum_md5,
+                filename: &entry.filename,
+                size_in_bytes: entry.size_in_bytes,
+                installed: config.is_mod_installed(&entry.checksum_md5),
+            })
+            .collect();
+        println!("{}", serde_json::to_string(&entries).unwrap());
+    } else {
+        for entry in mod_list.iter() {
+            let installed = match config.is_mod_installed(&entry.checksum_md5) {
+                true => "installed",
+                false => "-",
+            };
+            println!(
+                "{}  {:>6}M  {:<9}  {}",
+                entry.checksum_md5,
+                entry.size_in_bytes / 1024 / 1024,
+                installed,
+                entry.filename
+            );
+        }
+    }
+    Ok(EXIT_SUCCESS)
+}
+
+async fn install_command(
+    mods: &[String],
+    dry_run: bool,
+    args: &Args,
+    config: &mut ConfigObject,
+    manifest: &mut ManifestObject,
+    endpoints: &Endpoints,
+) -> Result<i32, CliError> {
+    set_assetto_path(&args.assetto_path, config)?;
+    let session = authenticate(args, config, endpoints).await?;
+    let mod_list = fetch_mod_list(&session, config).await?;
+    let selected_mods = select_mods(&mod_list, mods)?;
+
+    install(session, selected_mods, dry_run, args.json, config, manifest).await
+}
+
+async fn sync_command(
+    all: bool,
+    dry_run: bool,
+    args: &Args,
+    config: &mut ConfigObject,
+    manifest: &mut ManifestObject,
+    endpoints: &Endpoints,
+) -> Result<i32, CliError> {
+    if !all {
+        return Err(CliError::new(
+            EXIT_USAGE,
+            "Nothing to sync, use --all to install every mod from the server".to_string(),
+        ));
+    }
+
+    set_assetto_path(&args.assetto_path, config)?;
+    let session = authenticate(args, config, endpoints).await?;
+    let mod_list = fetch_mod_list(&session, config).await?;
+
+    install(session, mod_list, dry_run, args.json, config, manifest).await
+}
+
+fn uninstall_command(
+    mods: &[String],
+    args: &Args,
+    config: &mut ConfigObject,
+    manifest: &mut ManifestObject,
+) -> Result<i32, CliError> {
+    set_assetto_path(&args.assetto_path, config)?;
+
+    let mut checksums: Vec<String> = vec![];
+    for name in mods.iter() {
+        let entry = manifest
+            .manifest
+            .mods
+            .iter()
+            .find(|entry| entry.checksum_md5 == *name || entry.filename == *name);
+        let checksum = match entry {
+            Some(entry) => entry.checksum_md5.clone(),
+            None if config.is_mod_installed(name) => name.clone(),
+            None => {
+                return Err(CliError::new(
+                    EXIT_USAGE,
+                    format!("Mod {} is not installed", name),
+                ))
+            }
+        };
+        if !checksums.contains(&checksum) {
+            checksums.push(checksum);
+        }
+    }
+
+    let assetto_path = config.get_profile().assetto_path.clone();
+    let mut uninstalled_mods = vec![];
+    let mut error_list = vec![];
+    for checksum in checksums.iter() {
+        let filename = manifest
+            .get_mod(checksum)
+            .map(|mod_manifest| mod_manifest.filename.clone())
+            .unwrap_or_default();
+        let result = uninstall::uninstall_mod(Path::new(&assetto_path), checksum, config, manifest);
+        match result {
+            Ok(result) => {
+                if !args.json {
+                    println!(
+                        "Uninstalled {}, {} files removed, {} files kept",
+                        result.filename,
+                        result.removed_files.len(),
+                        result.kept_files.len()
+                    );
+                }
+                uninstalled_mods.push(result.filename);
+            }
+            Err(error) => error_list.push(ModError {
+                checksum_md5: checksum.clone(),
+                filename,
+                error,
+                attempts: 1,
+            }),
+        }
+    }
+
+    if args.json {
+        let summary = JsonUninstallSummary {
+            uninstalled: uninstalled_mods,
+            errors: to_json_errors(&error_list),
+        };
+        println!("{}", serde_json::to_string(&summary).unwrap());
+    } else if !error_list.is_empty() {
+        // the messages of uninstall_mod already name the mod
+        println!("Errors:");
+        for error in error_list.iter() {
+            println!("{}", error.error);
+        }
+    }
+
+    match error_list.len() {
+        0 => Ok(EXIT_SUCCESS),
+        _ => Ok(EXIT_INSTALL_FAILED),
+    }
+}
+
+fn restore_command(
+    session: &Option<String>,
+    args: &Args,
+    config: &mut ConfigObject,
+    manifest: &mut ManifestObject,
+) -> Result<i32, CliError> {
+    let backup_dir = config.get_backup_dir();
+    let sessions = backup::list_sessions(&backup_dir);
+
+    if session.is_none() {
+        if args.json {
+            let entries: Vec<JsonBackupSession> = sessions
+                .iter()
+                .map(|session| JsonBackupSession {
+                    id: &session.id,
+                    created: session.created,
+                    mods: session
+                        .mods
+                        .iter()
+                        .map(|entry| entry.filename.as_str())
+                        .collect(),
+                })
+                .collect();
+            println!("{}", serde_json::to_string(&entries).unwrap());
+        } else if sessions.is_empty() {
+            println!("There are no backups to restore.");
+        } else {
+            for session in sessions.iter() {
+                println!("{}", session.describe());
+            }
+        }
+        return Ok(EXIT_SUCCESS);
+    }
+
+    let id = session.as_ref().unwrap();
+    if !sessions.iter().any(|session| session.id == *id) {
+        return Err(CliError::new(
+            EXIT_USAGE,
+            format!("Backup {} not found", id),
+        ));
+    }
+    set_assetto_path(&args.assetto_path, config)?;
+
+    let assetto_path = config.get_profile().assetto_path.clone();
+    let result =
+        backup::restore_session(Path::new(&assetto_path), &backup_dir, id, config, manifest);
+    if let Err(error) = result {
+        return Err(CliError::from_error(error));
+    }
+    let result = result.unwrap();
+
+    if args.json {
+        let summary = JsonRestoreSummary {
+            session: id,
+            mods: result
+                .mods
+                .into_iter()
+                .map(|entry| entry.filename)
+                .collect(),
+            restored_files: result.restored_files,
+            removed_files: result.removed_files,
+        };
+        println!("{}", serde_json::to_string(&summary).unwrap());
+    } else {
+        println!(
+            "Backup {} restored, {} files restored, {} files removed",
+            id,
+            result.restored_files.len(),
+            result.removed_files.len()
+        );
+    }
+    Ok(EXIT_SUCCESS)
+}
+
+fn set_assetto_path(
+    assetto_path: &Option<String>,
+    config: &mut ConfigObject,
+) -> Result<(), CliError> {
+    let path = assetto_path
+        .clone()
+        .unwrap_or(config.get_profile().assetto_path.clone());
+    if !common::is_valid_assetto_path(Path::new(&path)) {
+        return Err(CliError::new(
+            EXIT_CONFIG,
+            format!(
+                "Path {:?} does not contain acs.exe, use --assetto-path",
+                path
+            ),
+        ));
+    }
+    config.set_assetto_path(path);
+    Ok(())
+}
+
+// An API token is used when one is set. Otherwise the session of an earlier run is
+// reused while the server accepts it, the password is only sent when it doesn't
+async fn authenticate(
+    args: &Args,
+    config: &mut ConfigObject,
+    endpoints: &Endpoints,
+) -> Result<Session, CliError> {
+    if let Some(api_token) = auth::get_api_token(&args.api_token, &config.get_api_token()) {
+        return server::token_session(endpoints, &api_token).map_err(CliError::from_error);
+    }
+
+    if config.get_profile().login.is_empty() {
+        return Err(CliError::new(
+            EXIT_AUTH,
+            "No stored credentials, run the login subcommand first or set an API token".to_string(),
+        ));
+    }
+
+    let login_data = LoginData {
+        login: config.get_profile().login.clone(),
+        password: config.get_password(),
+    };
+    if let Some(stored_session) = config.get_stored_session() {
+        match server::restore_session(endpoints, &login_data, &stored_session).await {
+            Ok(session) => return Ok(session),
+            Err(_) => eprintln!("Stored session not accepted, logging in again"),
+        }
+    }
+
+    let session = server::login(endpoints, &login_data).await;
+    if let Err(error) = session {
+        config.set_stored_session(None);
+        return Err(CliError::from_error(error));
+    }
+    let session = session.unwrap();
+    save_session(config, &session);
+    Ok(session)
+}
+
+// also keeps the session which the install thread logged in again with
+pub fn save_session(config: &mut ConfigObject, session: &Session) {
+    if let Some(stored_session) = session.get_stored_session() {
+        config.set_stored_session(Some(&stored_session));
+    }
+}
+
+async fn fetch_mod_list(
+    session: &Session,
+    config: &ConfigObject,
+) -> Result<Vec<JsonModTemplate>, CliError> {
+    let (mod_list, attempts) = server::get_mod_list(session, &config.get_retry_policy()).await;
+    match mod_list {
+        Ok(mod_list) => Ok(mod_list),
+        Err(error) => {
+            let error = CliError::from_error(error);
+            Err(CliError::new(
+                error.exit_code,
+                format!(
+                    "Error receiving mods: {}{}",
+                    error.message,
+                    retry::describe_attempts(attempts)
+                ),
+            ))
+        }
+    }
+}
+
+fn select_mods(
+    mod_list: &[JsonModTemplate],
+    names: &[String],
+) -> Result<Vec<JsonModTemplate>, CliError> {
+    let mut selected_mods: Vec<JsonModTemplate> = vec![];
+    for name in names.iter() {
+        let entry = mod_list
+            .iter()
+            .find(|entry| entry.checksum_md5 == *name || entry.filename == *name);
+        if entry.is_none() {
+            return Err(CliError::new(
+                EXIT_USAGE,
+                format!("Mod {} not found on the server", name),
+            ));
+        }
+        let entry = entry.unwrap();
+        if !selected_mods
+            .iter()
+            .any(|selected| selected.checksum_md5 == entry.checksum_md5)
+        {
+            selected_mods.push(entry.clone());
+        }
+    }
+    Ok(selected_mods)
+}
+
+// Returns false when the install thread stopped without finishing, e.g. after a panic
+async fn wait_for_install_thread(
+    install_thread: &InstallThread,
+    task_list: &[JsonModTemplate],
+    mut events: UnboundedReceiver<InstallEvent>,
+    mut handle: tokio::task::JoinHandle<()>,
+) -> bool {
+    // progress goes to stderr, so stdout stays machine-readable with --json,
+    // download events arrive per chunk so the status is printed at most every 100 ms
+    let mut progress = InstallProgress::new(task_list);
+    let mut last_status = String::new();
+    let mut interval = tokio::time::interval(Duration::from_millis(100));
+    let mut thread_ended = false;
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+    while !progress.is_finished() {
+        tokio::select! {
+            event = events.recv() => match event {
+                Some(event) => progress.apply(&event),
+                None => break,
+            },
+            // the thread sends Finished before it ends, unless it panicked
+            result = &mut handle, if !thread_ended => {
+                if result.is_err() {
+                    return false;
+                }
+                thread_ended = true;
+            }
+            // exiting here would skip the rollback of the running install and the
+            // recording of the mods which finished, so every Ctrl+C only cancels
+            _ = &mut ctrl_c => {
+                if install_thread.is_cancelled() {
+                    eprintln!("Still cancelling, waiting for the current step to finish");
+                } else {
+                    eprintln!("Cancelling, waiting for the current step to finish");
+                    install_thread.cancel();
+                }
+                ctrl_c.set(tokio::signal::ctrl_c());
+            }
+            _ = interval.tick() => {
+                let status = progress.describe();
+                if status != last_status {
+                    eprintln!("{}", status);
+                    last_status = status;
+                }
+            }
+        }
+    }
+    if !thread_ended && handle.await.is_err() {
+        return false;
+    }
+    progress.is_finished()
+}
+
+fn confirm(question: &str) -> bool {
+    eprint!("{} [y/N] ", question);
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    let answer = answer.trim().to_lowercase();
+    answer == "y" || answer == "yes"
+}
+
+// Returns the mods which should be installed after the plan is confirmed,
+// --json only prints the plan since stdin can't be used for confirmation then.
+// Archives kept for the install are dropped when none follows
+async fn dry_run(
+    session: Session,
+    task_list: Vec<JsonModTemplate>,
+    skipped_mods: &[String],
+    json: bool,
+    config: &ConfigObject,
+) -> Result<Vec<JsonModTemplate>, i32> {
+    let mut plan_thread = InstallThread::new(
+        session,
+        task_list.clone(),
+        config.config.download_concurrency,
+        config.get_unpack_limits(),
+        config.get_retry_policy(),
+        config.get_backup_dir(),
+    );
+    let events = plan_thread.subscribe();
+    let handle = plan_thread.start_dry_run(config.get_profile().assetto_path.clone());
+    let finished = wait_for_install_thread(&plan_thread, &task_list, events, handle).await;
+    if !finished {
+        eprintln!("The install plan stopped unexpectedly, nothing installed.");
+        return Err(EXIT_INSTALL_FAILED);
+    }
+    if plan_thread.is_cancelled() {
+        eprintln!("Cancelled, nothing installed.");
+        return Err(EXIT_CANCELLED);
+    }
+
+    let plans = plan_thread.get_install_plans();
+    let error_list = plan_thread.get_error_list();
+    if json {
+        let summary = JsonDryRunSummary {
+            plans: plans
+                .iter()
+                .map(|plan| JsonInstallPlan {
+                    checksum_md5: &plan.checksum_md5,
+                    filename: &plan.filename,
+                    tasks: plan
+                        .tasks
+                        .iter()
+                        .map(|task| JsonPlannedTask {
+                            source_path: &task.source_path,
+                            target_path: &task.target_path,
+                        })
+                        .collect(),
+                    file_count: plan.files.len(),
+                    overwritten_files: plan
+                        .get_overwritten_files()
+                        .iter()
+                        .map(|file| file.path.as_str())
+                        .collect(),
+                })
+                .collect(),
+            skipped: skipped_mods.to_vec(),
+            errors: to_json_errors(&error_list),
+        };
+        println!("{}", serde_json::to_string(&summary).unwrap());
+        plan_thread.discard_downloads();
+        return Err(match error_list.len() {
+            0 => EXIT_SUCCESS,
+            _ => EXIT_INSTALL_FAILED,
+        });
+    }
+
+    for plan in plans.iter() {
+        println!("{}", plan.describe());
+    }
+    if !skipped_mods.is_empty() {
+        println!("{} mods already installed.", skipped_mods.len());
+    }
+    if !error_list.is_empty() {
+        println!("Errors:");
+        for error in error_list.iter() {
+            println!("{}", error);
+        }
+    }
+    if plans.is_empty() || !confirm("Proceed with installation?") {
+        println!("Nothing installed.");
+        plan_thread.discard_downloads();
+        return Err(match error_list.len() {
+            0 => EXIT_SUCCESS,
+            _ => EXIT_INSTALL_FAILED,
+        });
+    }
+
+    Ok(task_list
+        .into_iter()
+        .filter(|entry| {
+            plans
+                .iter()
+                .any(|plan| plan.checksum_md5 == entry.checksum_md5)
+        })
+        .collect())
+}
+
+async fn install(
+    session: Session,
+    mods: Vec<JsonModTemplate>,
+    dry_run: bool,
+    json: bool,
+    config: &mut ConfigObject,
+    manifest: &mut ManifestObject,
+) -> Result<i32, CliError> {
+    let mut skipped_mods = vec![];
+    let mut task_list = vec![];
+    for entry in mods.into_iter() {
+        if config.is_mod_installed(&entry.checksum_md5) {
+            skipped_mods.push(entry.filename);
+        } else {
+            task_list.push(entry);
+        }
+    }
+
+    if dry_run {
+        let result = self::dry_run(session.clone(), task_list, &skipped_mods, json, config).await;
+        match result {
+            Ok(confirmed_tasks) => task_list = confirmed_tasks,
+            Err(exit_code) => {
+                save_session(config, &session);
+                return Ok(exit_code);
+            }
+        }
+    }
+
+    let mut install_thread = InstallThread::new(
+        session.clone(),
+        task_list.clone(),
+        config.config.download_concurrency,
+        config.get_unpack_limits(),
+        config.get_retry_policy(),
+        config.get_backup_dir(),
+    );
+    let events = install_thread.subscribe();
+    let handle = install_thread.start(config.get_profile().assetto_path.clone());
+    let finished = wait_for_install_thread(&install_thread, &task_list, events, handle).await;
+    save_session(config, &session);
+
+    let successfully_installed_mods = install_thread.get_successfully_installed_mods();
+    for checksum in successfully_installed_mods.iter() {
+        config.add_installed_mod(checksum);
+    }
+    // the mod is installed either way, only its file list couldn't be recorded
+    let mut manifest_errors = vec![];
+    for mod_manifest in install_thread.get_installed_mod_manifests() {
+        let checksum_md5 = mod_manifest.checksum_md5.clone();
+        let filename = mod_manifest.filename.clone();
+        if let Err(error) = manifest.add_mod(mod_manifest) {
+            manifest_errors.push(ModError {
+                checksum_md5,
+                filename,
+                error,
+                attempts: 1,
+            });
+        }
+    }
+    let mut installed_mods = vec![];
+    let mut failed_mods = vec![];
+    let mut unfinished_mods = vec![];
+    for (entry, outcome) in task_list.iter().zip(install_thread.get_mod_outcomes()) {
+        match outcome {
+            Some(ModOutcome::Succeeded) => installed_mods.push(entry.filename.clone()),
+            Some(ModOutcome::Failed(_)) => failed_mods.push(entry.filename.clone()),
+            Some(ModOutcome::Skipped) | None => unfinished_mods.push(entry.filename.clone()),
+        }
+    }
+    let mut error_list = install_thread.get_error_list();
+    error_list.extend(manifest_errors);
+    let backup_session = install_thread.get_backup_session_id();
+    let cancelled = install_thread.is_cancelled();
+    let install_failed = !finished || !failed_mods.is_empty() || !error_list.is_empty();
+
+    if json {
+        let summary = JsonInstallSummary {
+            installed: installed_mods,
+            skipped: skipped_mods,
+            failed: failed_mods,
+            unfinished: unfinished_mods,
+            errors: to_json_errors(&error_list),
+            backup_session,
+            cancelled,
+        };
+        println!("{}", serde_json::to_string(&summary).unwrap());
+    } else {
+        println!("{} mods installed successfully.", installed_mods.len());
+        if !skipped_mods.is_empty() {
+            println!("{} mods already installed.", skipped_mods.len());
+        }
+        if !failed_mods.is_empty() {
+            println!("{} mods failed to install.", failed_mods.len());
+        }
+        if !unfinished_mods.is_empty() {
+            println!("{} mods were not installed.", unfinished_mods.len());
+        }
+        if let Some(id) = backup_session {
+            println!("Replaced files were backed up as {}.", id);
+        }
+        if cancelled {
+            println!("Cancelled, the remaining mods were not installed.");
+        }
+        if !finished {
+            println!("The install stopped unexpectedly, the remaining mods were not installed.");
+        }
+        if !error_list.is_empty() {
+            println!("Errors:");
+            for error in error_list.iter() {
+                println!("{}", error);
+            }
+        }
+    }
+
+    if cancelled {
+        return Ok(EXIT_CANCELLED);
+    }
+    match install_failed {
+        false => Ok(EXIT_SUCCESS),
+        true => Ok(EXIT_INSTALL_FAILED),
+    }
+}
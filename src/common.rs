@@ -1,41 +1,291 @@
-use std::{fs::File, path::Path};
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{self, Read, Write},
+    path::{Component, Path},
+};
 use walkdir::WalkDir;
 
-pub fn rar_unpack(archive_path: &Path, destination_path: &Path) -> compress_tools::Result<()> {
+use crate::error::{Error, Result};
+
+pub fn is_valid_assetto_path(path: &Path) -> bool {
+    path.join("acs.exe").exists()
+}
+
+#[derive(Clone, Debug)]
+pub struct UnpackLimits {
+    pub max_size_in_bytes: u64,
+    pub max_file_count: usize,
+}
+
+pub struct ArchiveSummary {
+    pub entry_names: Vec<String>,
+    pub unpacked_size_in_bytes: u64,
+}
+
+// unrar extracts one entry per iteration, the limits are checked against the size of
+// the files written so far since the sizes in the headers can't be trusted
+pub fn rar_unpack(
+    archive_path: &Path,
+    destination_path: &Path,
+    limits: &UnpackLimits,
+) -> Result<()> {
     let path_as_string = archive_path.to_str().unwrap().to_string();
     let archive = unrar::Archive::new(path_as_string);
     let result = archive.extract_to(destination_path.to_str().unwrap().to_string());
     if let Err(error) = result {
-        return Err(compress_tools::Error::from(error.to_string()));
+        return Err(Error::Unpack(error.to_string()));
     }
-    let mut open_archive = result.unwrap();
-    let process_result = open_archive.process();
-    if let Err(error) = process_result {
-        return Err(compress_tools::Error::from(error.to_string()));
+
+    let mut summary = ArchiveSummary {
+        entry_names: vec![],
+        unpacked_size_in_bytes: 0,
+    };
+    for entry in result.unwrap() {
+        if let Err(error) = entry {
+            return Err(Error::Unpack(error.to_string()));
+        }
+        let entry = entry.unwrap();
+        if !entry.is_directory() {
+            summary.unpacked_size_in_bytes +=
+                std::fs::symlink_metadata(destination_path.join(&entry.filename))
+                    .map(|metadata| metadata.len())
+                    .unwrap_or(0);
+        }
+        summary.entry_names.push(entry.filename.to_string());
+        limits.check(&summary)?;
     }
     Ok(())
 }
 
-pub fn compress_tools_unpack(
-    archive_path: &Path,
-    destination_path: &Path,
-) -> compress_tools::Result<()> {
+impl UnpackLimits {
+    fn check(&self, summary: &ArchiveSummary) -> Result<()> {
+        if summary.unpacked_size_in_bytes > self.max_size_in_bytes {
+            return Err(Error::Unpack(format!(
+                "archive expands to more than {} MB, see max_unpacked_size_mb in the config",
+                self.max_size_in_bytes / 1024 / 1024
+            )));
+        }
+        if summary.entry_names.len() > self.max_file_count {
+            return Err(Error::Unpack(format!(
+                "archive contains more than {} entries, see max_unpacked_file_count in the config",
+                self.max_file_count
+            )));
+        }
+        Ok(())
+    }
+}
+
+// unrar only lists the sizes stored in the headers, they reject an archive early and
+// size the free space check, rar_unpack enforces the limits on what is really written
+fn rar_scan(archive_path: &Path, limits: &UnpackLimits) -> Result<ArchiveSummary> {
+    let path_as_string = archive_path.to_str().unwrap().to_string();
+    let archive = unrar::Archive::new(path_as_string).list();
+    if let Err(error) = archive {
+        return Err(Error::Unpack(error.to_string()));
+    }
+
+    let mut summary = ArchiveSummary {
+        entry_names: vec![],
+        unpacked_size_in_bytes: 0,
+    };
+    for entry in archive.unwrap() {
+        if let Err(error) = entry {
+            return Err(Error::Unpack(error.to_string()));
+        }
+        let entry = entry.unwrap();
+        let mut name = entry.filename.to_string();
+        if entry.is_directory() {
+            name.push('/');
+        }
+        summary.entry_names.push(name);
+        summary.unpacked_size_in_bytes += entry.unpacked_size as u64;
+        limits.check(&summary)?;
+    }
+    Ok(summary)
+}
+
+// Sizes stored in archive headers can't be trusted, so the archive is decompressed
+// without writing anything and stops as soon as a limit is exceeded
+fn compress_tools_scan(archive_path: &Path, limits: &UnpackLimits) -> Result<ArchiveSummary> {
+    let source = File::open(archive_path)?;
+    let mut summary = ArchiveSummary {
+        entry_names: vec![],
+        unpacked_size_in_bytes: 0,
+    };
+    for content in compress_tools::ArchiveIterator::from_read(source)? {
+        match content {
+            compress_tools::ArchiveContents::StartOfEntry(name) => {
+                summary.entry_names.push(name);
+            }
+            compress_tools::ArchiveContents::DataChunk(data) => {
+                summary.unpacked_size_in_bytes += data.len() as u64;
+            }
+            compress_tools::ArchiveContents::EndOfEntry => continue,
+            compress_tools::ArchiveContents::Err(error) => return Err(Error::from(error)),
+        }
+        limits.check(&summary)?;
+    }
+    Ok(summary)
+}
+
+// a directory which doesn't exist yet is created on the filesystem of its closest
+// existing parent
+pub fn check_free_space(path: &Path, required_bytes: u64) -> Result<()> {
+    let existing_path = path
+        .ancestors()
+        .find(|ancestor| ancestor.exists())
+        .unwrap_or(path);
+    let available_bytes = fs2::available_space(existing_path);
+    if let Err(error) = available_bytes {
+        return Err(Error::Filesystem(format!(
+            "cannot check free space on {}: {}",
+            path.display(),
+            error
+        )));
+    }
+    let available_bytes = available_bytes.unwrap();
+    if available_bytes < required_bytes {
+        return Err(Error::Filesystem(format!(
+            "not enough free space on {}, {} MB required, {} MB available",
+            path.display(),
+            required_bytes / 1024 / 1024,
+            available_bytes / 1024 / 1024
+        )));
+    }
+    Ok(())
+}
+
+pub fn compress_tools_unpack(archive_path: &Path, destination_path: &Path) -> Result<()> {
     let mut source = File::open(archive_path)?;
     compress_tools::uncompress_archive(
         &mut source,
         destination_path,
         compress_tools::Ownership::Ignore,
-    )
+    )?;
+    Ok(())
 }
 
-pub fn unpack_archive(archive_path: &Path, destination_path: &Path) -> compress_tools::Result<()> {
+fn is_escaping_entry_name(name: &str) -> bool {
+    if name.starts_with('/') || name.starts_with('\\') {
+        return true;
+    }
+    // "C:" as the first component is a Windows drive
+    name.split(['/', '\\'])
+        .enumerate()
+        .any(|(index, component)| component == ".." || (index == 0 && component.ends_with(':')))
+}
+
+// Entry names are checked before anything is written, a symlink entry followed by
+// entries below it would make the extraction write through the symlink
+pub fn validate_archive_entries(entry_names: &[String]) -> Result<()> {
+    let mut parent_dirs = HashSet::new();
+    for name in entry_names.iter() {
+        if name.is_empty() || is_escaping_entry_name(name) {
+            return Err(Error::Unpack(format!(
+                "unsafe archive entry \"{}\": path points outside of the unpack directory",
+                name
+            )));
+        }
+        let name = name.replace('\\', "/");
+        let mut parent = Path::new(name.trim_end_matches('/')).parent();
+        while let Some(dir) = parent {
+            if dir.as_os_str().is_empty() || !parent_dirs.insert(dir.to_path_buf()) {
+                break;
+            }
+            parent = dir.parent();
+        }
+    }
+
+    for name in entry_names.iter() {
+        let name = name.replace('\\', "/");
+        if !name.ends_with('/') && parent_dirs.contains(Path::new(&name)) {
+            return Err(Error::Unpack(format!(
+                "unsafe archive entry \"{}\": file is used as a directory by other entries, it may be a symlink",
+                name
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn is_escaping_symlink(destination_path: &Path, link_path: &Path, target: &Path) -> bool {
+    if target.has_root() {
+        return true;
+    }
+    let link_dir = link_path.parent().unwrap_or(destination_path);
+    let mut depth = match link_dir.strip_prefix(destination_path) {
+        Ok(relative_dir) => relative_dir.components().count() as i64,
+        Err(_) => return true,
+    };
+    for component in target.components() {
+        match component {
+            Component::ParentDir => depth -= 1,
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::Prefix(_) | Component::RootDir => return true,
+        }
+        if depth < 0 {
+            return true;
+        }
+    }
+    false
+}
+
+pub fn validate_unpacked_symlinks(destination_path: &Path) -> Result<()> {
+    for entry in WalkDir::new(destination_path) {
+        let entry = entry?;
+        if !entry.path_is_symlink() {
+            continue;
+        }
+        let target = std::fs::read_link(entry.path());
+        if let Err(error) = target {
+            return Err(Error::Filesystem(format!(
+                "cannot read symlink {}: {}",
+                entry.path().display(),
+                error
+            )));
+        }
+        let target = target.unwrap();
+        if is_escaping_symlink(destination_path, entry.path(), &target) {
+            return Err(Error::Unpack(format!(
+                "unsafe archive entry \"{}\": symlink to {} points outside of the unpack directory",
+                to_relative_path_string(entry.path().strip_prefix(destination_path).unwrap()),
+                target.display()
+            )));
+        }
+    }
+    Ok(())
+}
+
+// Nothing is written before the entry names, limits and free space are checked,
+// space_check_paths are filesystems which receive the unpacked files later on
+pub fn unpack_archive(
+    archive_path: &Path,
+    destination_path: &Path,
+    limits: &UnpackLimits,
+    space_check_paths: &[&Path],
+) -> Result<()> {
     // compress_tools doesn't work with some rar archives, so unrar is used
-    let extension = archive_path.extension().unwrap();
-    if extension == "rar" {
-        rar_unpack(archive_path, destination_path)
+    let extension = archive_path.extension().unwrap_or_default();
+    let is_rar = extension == "rar";
+    let summary = match is_rar {
+        true => rar_scan(archive_path, limits)?,
+        false => compress_tools_scan(archive_path, limits)?,
+    };
+    validate_archive_entries(&summary.entry_names)?;
+    for path in std::iter::once(&destination_path).chain(space_check_paths.iter()) {
+        check_free_space(path, summary.unpacked_size_in_bytes)?;
+    }
+
+    if is_rar {
+        rar_unpack(archive_path, destination_path, limits)?;
     } else {
-        compress_tools_unpack(archive_path, destination_path)
+        compress_tools_unpack(archive_path, destination_path)?;
     }
+
+    validate_unpacked_symlinks(destination_path)?;
+    Ok(())
 }
 
 pub struct FsEntry {
@@ -54,3 +304,184 @@ pub fn recursive_ls(dir: &Path) -> Vec<FsEntry> {
     }
     ret
 }
+
+pub fn md5_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut md5_context = md5::Context::new();
+    let mut buffer = vec![0u8; 65536];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        md5_context.consume(&buffer[..read]);
+    }
+    Ok(format!("{:x}", md5_context.compute()))
+}
+
+// The new content goes to a temporary file which replaces the file in one step,
+// a crash while writing leaves the previous content in place
+pub fn write_atomically(path: &Path, content: &[u8]) -> io::Result<()> {
+    let temp_path = format!("{}.tmp", path.display());
+    let mut file = File::create(&temp_path)?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    std::fs::rename(&temp_path, path)
+}
+
+// Paths relative to the Assetto Corsa directory are stored with '/' separators,
+// so they don't depend on the platform the mod was installed on
+pub fn to_relative_path_string(path: &Path) -> String {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy().to_string())
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::fixture_path;
+    use tempdir::TempDir;
+
+    fn limits() -> UnpackLimits {
+        UnpackLimits {
+            max_size_in_bytes: 1024 * 1024,
+            max_file_count: 100,
+        }
+    }
+
+    fn names(entries: &[&str]) -> Vec<String> {
+        entries.iter().map(|entry| entry.to_string()).collect()
+    }
+
+    #[test]
+    fn test_validate_archive_entries() {
+        assert!(validate_archive_entries(&names(&[
+            "content/",
+            "content/cars/",
+            "content/cars/a/data.txt",
+            "extension\\config\\a.ini",
+        ]))
+        .is_ok());
+
+        assert!(validate_archive_entries(&names(&["../evil.txt"])).is_err());
+        assert!(validate_archive_entries(&names(&["content/../../evil.txt"])).is_err());
+        assert!(validate_archive_entries(&names(&["content\\..\\..\\evil.txt"])).is_err());
+        assert!(validate_archive_entries(&names(&["/tmp/evil.txt"])).is_err());
+        assert!(validate_archive_entries(&names(&["\\tmp\\evil.txt"])).is_err());
+        assert!(validate_archive_entries(&names(&["C:\\evil.txt"])).is_err());
+        assert!(validate_archive_entries(&names(&["link", "link/evil.txt"])).is_err());
+    }
+
+    #[test]
+    fn test_unpack_valid_archives() {
+        for name in ["valid.zip", "valid.7z", "valid.rar"].iter() {
+            let temp_dir = TempDir::new("unpack_test").unwrap();
+            unpack_archive(&fixture_path(name), temp_dir.path(), &limits(), &[]).unwrap();
+            assert_eq!(
+                std::fs::read_to_string(temp_dir.path().join("content/cars/a/data.txt")).unwrap(),
+                "data",
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_unpack_rejects_escaping_paths() {
+        for name in [
+            "traversal.zip",
+            "absolute.zip",
+            "traversal.7z",
+            "traversal.rar",
+        ]
+        .iter()
+        {
+            let temp_dir = TempDir::new("unpack_test").unwrap();
+            let destination_path = temp_dir.path().join("unpack");
+            std::fs::create_dir(&destination_path).unwrap();
+
+            let error =
+                unpack_archive(&fixture_path(name), &destination_path, &limits(), &[]).unwrap_err();
+            assert!(
+                error
+                    .to_string()
+                    .contains("points outside of the unpack directory"),
+                "{}: {}",
+                name,
+                error
+            );
+            assert_eq!(std::fs::read_dir(&destination_path).unwrap().count(), 0);
+            assert!(!temp_dir.path().join("evil.txt").exists());
+        }
+    }
+
+    #[test]
+    fn test_unpack_rejects_symlinks() {
+        for name in ["symlink.zip", "symlink.7z", "symlink.rar"].iter() {
+            let temp_dir = TempDir::new("unpack_test").unwrap();
+            let error =
+                unpack_archive(&fixture_path(name), temp_dir.path(), &limits(), &[]).unwrap_err();
+            assert!(
+                error.to_string().contains("unsafe archive entry"),
+                "{}: {}",
+                name,
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn test_unpack_limits() {
+        for name in ["valid.zip", "valid.7z", "valid.rar"].iter() {
+            let temp_dir = TempDir::new("unpack_test").unwrap();
+            let size_limit = UnpackLimits {
+                max_size_in_bytes: 3,
+                max_file_count: 100,
+            };
+            let error =
+                unpack_archive(&fixture_path(name), temp_dir.path(), &size_limit, &[]).unwrap_err();
+            assert!(
+                error.to_string().contains("max_unpacked_size_mb"),
+                "{}",
+                name
+            );
+
+            let count_limit = UnpackLimits {
+                max_size_in_bytes: 1024,
+                max_file_count: 0,
+            };
+            let error = unpack_archive(&fixture_path(name), temp_dir.path(), &count_limit, &[])
+                .unwrap_err();
+            assert!(
+                error.to_string().contains("max_unpacked_file_count"),
+                "{}",
+                name
+            );
+            assert_eq!(std::fs::read_dir(temp_dir.path()).unwrap().count(), 0);
+        }
+    }
+
+    #[test]
+    fn test_check_free_space() {
+        let temp_dir = TempDir::new("space_test").unwrap();
+        assert!(check_free_space(temp_dir.path(), 1).is_ok());
+        assert!(check_free_space(temp_dir.path(), u64::MAX).is_err());
+        // backup directories are only created when the first file is backed up
+        assert!(check_free_space(&temp_dir.path().join("backups/1"), 1).is_ok());
+    }
+
+    #[test]
+    fn test_rar_unpack_counts_written_bytes() {
+        let temp_dir = TempDir::new("unpack_test").unwrap();
+        let size_limit = UnpackLimits {
+            max_size_in_bytes: 3,
+            max_file_count: 100,
+        };
+        let error =
+            rar_unpack(&fixture_path("valid.rar"), temp_dir.path(), &size_limit).unwrap_err();
+        assert!(error.to_string().contains("max_unpacked_size_mb"));
+        assert!(rar_unpack(&fixture_path("valid.rar"), temp_dir.path(), &limits()).is_ok());
+    }
+}
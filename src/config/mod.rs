@@ -1,68 +1,615 @@
-use std::path::Path;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Default, Serialize, Deserialize)]
-pub struct Config {
+use crate::backup;
+use crate::common::{write_atomically, UnpackLimits};
+use crate::endpoints::DEFAULT_SERVER_URL;
+use crate::error::{Error, Result};
+use crate::retry::RetryPolicy;
+use crate::secret_store::{self, SecretStore};
+use crate::server::StoredSession;
+
+pub const DEFAULT_PROFILE: &str = "default";
+
+// written to every config file, older files are brought up to it by MIGRATIONS
+pub const CONFIG_VERSION: u32 = 1;
+
+// Everything which belongs to one server and game installation, a machine can
+// sync several of them
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
     pub assetto_path: String,
     pub login: String,
     pub installed_mods_md5: Vec<String>,
+    #[serde(default = "default_server_url")]
+    pub server_url: String,
+    // only read to move a token put here into the secret store, see migrate_api_tokens
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub api_token: String,
+}
+
+impl Default for Profile {
+    fn default() -> Profile {
+        Profile {
+            assetto_path: String::default(),
+            login: String::default(),
+            installed_mods_md5: vec![],
+            server_url: default_server_url(),
+            api_token: String::default(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Config {
+    // missing in files of versions before it was added
+    #[serde(default)]
+    pub version: u32,
+    #[serde(default = "default_profile_name")]
+    pub selected_profile: String,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+    // settings of versions without profiles, only read by migrate_profiles
+    #[serde(default, skip_serializing)]
+    assetto_path: Option<String>,
+    #[serde(default, skip_serializing)]
+    login: Option<String>,
+    #[serde(default, skip_serializing)]
+    installed_mods_md5: Option<Vec<String>>,
+    #[serde(default, skip_serializing)]
+    server_url: Option<String>,
+    #[serde(default, skip_serializing)]
+    api_token: Option<String>,
+    // only read to migrate passwords stored in plaintext, see migrate_password
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub password: String,
+    #[serde(default = "default_download_concurrency")]
+    pub download_concurrency: usize,
+    #[serde(default = "default_max_unpacked_size_mb")]
+    pub max_unpacked_size_mb: u64,
+    #[serde(default = "default_max_unpacked_file_count")]
+    pub max_unpacked_file_count: usize,
+    #[serde(default = "default_retry_attempts")]
+    pub retry_attempts: u32,
+    #[serde(default = "default_retry_backoff_ms")]
+    pub retry_backoff_ms: u64,
+    #[serde(default = "default_retry_max_backoff_ms")]
+    pub retry_max_backoff_ms: u64,
+    #[serde(default = "default_retry_http_statuses")]
+    pub retry_http_statuses: Vec<u16>,
+}
+
+// Read before the rest of the file, a newer version may have changed the layout so
+// that Config can't read it
+#[derive(Deserialize)]
+struct ConfigVersion {
+    #[serde(default)]
+    version: u32,
+}
+
+fn default_profile_name() -> String {
+    DEFAULT_PROFILE.to_string()
+}
+
+fn default_server_url() -> String {
+    DEFAULT_SERVER_URL.to_string()
+}
+
+fn default_download_concurrency() -> usize {
+    2
+}
+
+fn default_max_unpacked_size_mb() -> u64 {
+    20 * 1024
+}
+
+fn default_max_unpacked_file_count() -> usize {
+    100000
+}
+
+fn default_retry_attempts() -> u32 {
+    3
+}
+
+fn default_retry_backoff_ms() -> u64 {
+    1000
+}
+
+fn default_retry_max_backoff_ms() -> u64 {
+    30000
+}
+
+fn default_retry_http_statuses() -> Vec<u16> {
+    vec![408, 429, 500, 502, 503, 504]
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            version: CONFIG_VERSION,
+            selected_profile: default_profile_name(),
+            profiles: BTreeMap::new(),
+            assetto_path: None,
+            login: None,
+            installed_mods_md5: None,
+            server_url: None,
+            api_token: None,
+            password: String::default(),
+            download_concurrency: default_download_concurrency(),
+            max_unpacked_size_mb: default_max_unpacked_size_mb(),
+            max_unpacked_file_count: default_max_unpacked_file_count(),
+            retry_attempts: default_retry_attempts(),
+            retry_backoff_ms: default_retry_backoff_ms(),
+            retry_max_backoff_ms: default_retry_max_backoff_ms(),
+            retry_http_statuses: default_retry_http_statuses(),
+        }
+    }
 }
 
 pub trait ConfigTrait {
     fn new(path: &str) -> Self;
     fn add_installed_mod(&mut self, md5: &String);
+    fn get_api_token(&self) -> String;
+    fn get_backup_dir(&self) -> PathBuf;
+    fn get_manifest_path(&self) -> String;
+    fn get_password(&self) -> String;
+    fn get_profile(&self) -> &Profile;
+    fn get_profile_mut(&mut self) -> &mut Profile;
+    fn get_profile_name(&self) -> &str;
+    fn get_profile_names(&self) -> Vec<String>;
+    fn get_retry_policy(&self) -> RetryPolicy;
+    fn get_stored_session(&self) -> Option<StoredSession>;
+    fn get_unpack_limits(&self) -> UnpackLimits;
+    fn get_warnings(&self) -> &Vec<String>;
     fn is_mod_installed(&self, md5: &String) -> bool;
+    fn remove_installed_mod(&mut self, md5: &str);
+    fn select_profile(&mut self, name: &str) -> Result<bool>;
     fn set_assetto_path(&mut self, path: String);
     fn set_login(&mut self, login: String);
     fn set_password(&mut self, password: String);
+    fn set_server_url(&mut self, server_url: String);
+    fn set_stored_session(&mut self, session: Option<&StoredSession>);
 }
 
 pub struct ConfigObject {
     pub config: Config,
     pub path: String,
+    profile: String,
+    secrets: Box<dyn SecretStore>,
+    // problems found while loading, for the user to see
+    warnings: Vec<String>,
+    // set for a config of a newer version, writing it would lose the settings this
+    // version doesn't know
+    read_only: bool,
+    // whether this run already copied the loaded file to the .bak
+    backed_up: bool,
 }
 
-fn read_config(path: &str) -> String {
-    let file = std::fs::read_to_string(path);
-    match file {
-        Ok(file) => file,
-        Err(_) => serde_json::to_string(&Config::default()).unwrap(),
+fn get_backup_path(path: &str) -> String {
+    format!("{}.bak", path)
+}
+
+// Copies of the config file are written without the password an older version kept
+// in it and without API tokens, they belong in the secret store only. None when the
+// content isn't JSON
+fn remove_secrets(content: &str) -> Option<String> {
+    let mut value: serde_json::Value = serde_json::from_str(content).ok()?;
+    if let Some(object) = value.as_object_mut() {
+        object.remove("password");
+        object.remove("api_token");
+        if let Some(profiles) = object.get_mut("profiles").and_then(|p| p.as_object_mut()) {
+            for profile in profiles.values_mut().filter_map(|p| p.as_object_mut()) {
+                profile.remove("api_token");
+            }
+        }
+    }
+    serde_json::to_string_pretty(&value).ok()
+}
+
+// the content goes to the copy without secrets, or as it is when it can't be read
+fn copy_config(path: &str, copy_path: &str) -> std::io::Result<()> {
+    let content = std::fs::read_to_string(path)?;
+    let content = remove_secrets(&content).unwrap_or(content);
+    write_atomically(Path::new(copy_path), content.as_bytes())
+}
+
+// An unreadable config is moved aside instead of being overwritten, so nothing in it
+// is lost, and the default settings are used
+fn quarantine_config(path: &str, reason: String) -> (Config, Vec<String>) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    let quarantine_path = format!("{}.broken-{}", path, now);
+    let moved = copy_config(path, &quarantine_path).and_then(|_| std::fs::remove_file(path));
+    let moved = moved.or_else(|_| std::fs::rename(path, &quarantine_path));
+    let mut warning = match moved {
+        Ok(()) => format!(
+            "Cannot read the config file {} ({}), it was moved to {} and the default settings are used",
+            path, reason, quarantine_path
+        ),
+        Err(error) => format!(
+            "Cannot read the config file {} ({}) and cannot move it away ({}), the default settings are used",
+            path, reason, error
+        ),
+    };
+    let backup_path = get_backup_path(path);
+    if Path::new(&backup_path).exists() {
+        warning += &format!(". The settings of an earlier run are in {}", backup_path);
+    }
+    (Config::default(), vec![warning])
+}
+
+// a missing config is a first run, not an error
+fn read_config(path: &str) -> (Config, Vec<String>) {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+            return (Config::default(), vec![])
+        }
+        Err(error) => return quarantine_config(path, error.to_string()),
+    };
+    let version = match serde_json::from_str::<ConfigVersion>(&content) {
+        Ok(config_version) => config_version.version,
+        Err(error) => return quarantine_config(path, error.to_string()),
+    };
+
+    // the file of a newer version is never replaced, only read as far as possible
+    if version > CONFIG_VERSION {
+        let warning = format!(
+            "The config file {} was written by a newer version of the client, it is only read and changes are not saved",
+            path
+        );
+        let config = match serde_json::from_str(&content) {
+            Ok(config) => config,
+            Err(error) => {
+                let warning = format!(
+                    "{}. Its settings cannot be read ({}), the default settings are used",
+                    warning, error
+                );
+                let config = Config {
+                    version,
+                    ..Config::default()
+                };
+                return (config, vec![warning]);
+            }
+        };
+        return (config, vec![warning]);
+    }
+    match serde_json::from_str(&content) {
+        Ok(config) => (config, vec![]),
+        Err(error) => quarantine_config(path, error.to_string()),
     }
 }
 
 fn write_config_to_json(path: &Path, config: &Config) {
     let json = serde_json::to_string_pretty(config);
     if let Ok(output) = json {
-        std::fs::write(path, output).expect("Config file writing failure");
+        write_atomically(path, output.as_bytes()).expect("Config file writing failure");
     }
 }
 
-impl ConfigTrait for ConfigObject {
-    fn new(path: &str) -> ConfigObject {
-        let content = read_config(path);
-        let config: Config = serde_json::from_str(&content).unwrap();
-        write_config_to_json(Path::new(path), &config);
+fn get_config_dir(path: &str) -> &Path {
+    Path::new(path).parent().unwrap_or(Path::new("."))
+}
+
+// the manifest of every profile is kept next to the config file
+fn get_manifest_file_name(profile: &str) -> String {
+    format!("assetto_sync_manifest.{}.json", profile)
+}
+
+// profile names become part of file names
+fn is_valid_profile_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn move_secret(secrets: &mut dyn SecretStore, from: &str, to: &str) {
+    let result = match secrets.get_secret(from) {
+        Ok(Some(secret)) => secrets
+            .set_secret(to, &secret)
+            .and_then(|_| secrets.delete_secret(from)),
+        Ok(None) => Ok(()),
+        Err(error) => Err(error),
+    };
+    if let Err(error) = result {
+        eprintln!("Cannot move {} in {}: {}", from, secrets.get_name(), error);
+    }
+}
+
+// Older versions had a single set of settings, they become the default profile
+// together with the stored password, session, manifest and backups
+fn migrate_profiles(
+    config: &mut Config,
+    secrets: &mut dyn SecretStore,
+    config_dir: &Path,
+    backup_root: &Path,
+) {
+    if !config.profiles.is_empty() {
+        return;
+    }
+    let login = config.login.take();
+    let profile = Profile {
+        assetto_path: config.assetto_path.take().unwrap_or_default(),
+        login: login.clone().unwrap_or_default(),
+        installed_mods_md5: config.installed_mods_md5.take().unwrap_or_default(),
+        server_url: config.server_url.take().unwrap_or(default_server_url()),
+        api_token: config.api_token.take().unwrap_or_default(),
+    };
+    backup::move_legacy_sessions(backup_root, DEFAULT_PROFILE, &profile.assetto_path);
+    config.selected_profile = default_profile_name();
+    config.profiles.insert(default_profile_name(), profile);
 
-        ConfigObject {
+    // a new config has nothing to move
+    let login = match login {
+        Some(login) => login,
+        None => return,
+    };
+    eprintln!("Moved the settings to the \"{}\" profile", DEFAULT_PROFILE);
+    move_secret(
+        secrets,
+        &format!("password:{}", login),
+        &secret_store::password_secret_name(DEFAULT_PROFILE, &login),
+    );
+    move_secret(
+        secrets,
+        &format!("session:{}", login),
+        &secret_store::session_secret_name(DEFAULT_PROFILE, &login),
+    );
+    let manifest_path = config_dir.join("assetto_sync_manifest.json");
+    let profile_manifest_path = config_dir.join(get_manifest_file_name(DEFAULT_PROFILE));
+    if manifest_path.exists() && !profile_manifest_path.exists() {
+        if let Err(error) = std::fs::rename(&manifest_path, &profile_manifest_path) {
+            eprintln!(
+                "Cannot move {} to {}: {}",
+                manifest_path.display(),
+                profile_manifest_path.display(),
+                error
+            );
+        }
+    }
+}
+
+type Migration = fn(&mut Config, &mut dyn SecretStore, &Path, &Path);
+
+// MIGRATIONS[n] brings a config of version n to version n + 1
+const MIGRATIONS: [Migration; CONFIG_VERSION as usize] = [migrate_profiles];
+
+fn migrate_config(
+    config: &mut Config,
+    secrets: &mut dyn SecretStore,
+    config_dir: &Path,
+    backup_root: &Path,
+) {
+    for version in config.version..CONFIG_VERSION {
+        MIGRATIONS[version as usize](config, secrets, config_dir, backup_root);
+        config.version = version + 1;
+    }
+}
+
+// Older versions kept the password in the config file, it is moved to the secret
+// store of the default profile and left in place only when that fails
+fn migrate_password(config: &mut Config, secrets: &mut dyn SecretStore) {
+    if config.password.is_empty() {
+        return;
+    }
+    let login = match config.profiles.get(DEFAULT_PROFILE) {
+        Some(profile) => profile.login.clone(),
+        None => return,
+    };
+    let name = secret_store::password_secret_name(DEFAULT_PROFILE, &login);
+    match secrets.set_secret(&name, &config.password) {
+        Ok(()) => {
+            eprintln!("Moved the stored password to {}", secrets.get_name());
+            config.password.clear();
+        }
+        Err(error) => eprintln!(
+            "Cannot move the stored password to {}: {}",
+            secrets.get_name(),
+            error
+        ),
+    }
+}
+
+// A token in the config file, put there by hand or by an older version, is moved to
+// the secret store of its profile and left in place only when that fails
+fn migrate_api_tokens(config: &mut Config, secrets: &mut dyn SecretStore) {
+    for (name, profile) in config.profiles.iter_mut() {
+        if profile.api_token.is_empty() {
+            continue;
+        }
+        let secret_name = secret_store::api_token_secret_name(name);
+        match secrets.set_secret(&secret_name, &profile.api_token) {
+            Ok(()) => {
+                eprintln!(
+                    "Moved the API token of profile {} to {}",
+                    name,
+                    secrets.get_name()
+                );
+                profile.api_token.clear();
+            }
+            Err(error) => eprintln!(
+                "Cannot move the API token of profile {} to {}: {}",
+                name,
+                secrets.get_name(),
+                error
+            ),
+        }
+    }
+}
+
+impl ConfigObject {
+    fn password_secret_name(&self) -> String {
+        secret_store::password_secret_name(&self.profile, &self.get_profile().login)
+    }
+
+    fn session_secret_name(&self) -> String {
+        secret_store::session_secret_name(&self.profile, &self.get_profile().login)
+    }
+
+    // new() with the secret store and the backup root given, so tests don't touch
+    // the ones of the user
+    pub fn open(path: &str, mut secrets: Box<dyn SecretStore>, backup_root: &Path) -> ConfigObject {
+        let (mut config, warnings) = read_config(path);
+        let read_only = config.version > CONFIG_VERSION;
+        if !read_only {
+            migrate_config(
+                &mut config,
+                secrets.as_mut(),
+                get_config_dir(path),
+                backup_root,
+            );
+            migrate_password(&mut config, secrets.as_mut());
+            migrate_api_tokens(&mut config, secrets.as_mut());
+        }
+
+        // the selected profile may have been removed from the file by hand
+        let profile = config.selected_profile.clone();
+        config.profiles.entry(profile.clone()).or_default();
+
+        let mut config_object = ConfigObject {
             config: config,
             path: path.to_string(),
+            profile,
+            secrets,
+            warnings,
+            read_only,
+            backed_up: false,
+        };
+        config_object.save();
+        config_object
+    }
+
+    // Only writes when something changed. Before the first write of a run the file is
+    // copied to the .bak, so it keeps the config as it was before this run
+    fn save(&mut self) {
+        if self.read_only {
+            return;
+        }
+        let json = serde_json::to_string_pretty(&self.config).unwrap();
+        if std::fs::read_to_string(&self.path).ok() == Some(json) {
+            return;
         }
+        if !self.backed_up && Path::new(&self.path).exists() {
+            if let Err(error) = copy_config(&self.path, &get_backup_path(&self.path)) {
+                eprintln!("Cannot back up the config file {}: {}", self.path, error);
+            }
+        }
+        self.backed_up = true;
+        write_config_to_json(Path::new(&self.path), &self.config);
+    }
+}
+
+impl ConfigTrait for ConfigObject {
+    fn new(path: &str) -> ConfigObject {
+        let secrets = secret_store::open_secret_store(get_config_dir(path));
+        ConfigObject::open(path, secrets, &backup::get_backup_root())
     }
 
     fn add_installed_mod(&mut self, md5: &String) {
-        for checksum in self.config.installed_mods_md5.iter() {
-            if *md5 == *checksum {
-                return;
+        if self.is_mod_installed(md5) {
+            return;
+        }
+        self.get_profile_mut().installed_mods_md5.push(md5.clone());
+        self.save();
+    }
+
+    // empty when the profile has no token, one which couldn't be moved to the secret
+    // store is still in the config
+    fn get_api_token(&self) -> String {
+        if !self.get_profile().api_token.is_empty() {
+            return self.get_profile().api_token.clone();
+        }
+        let name = secret_store::api_token_secret_name(&self.profile);
+        match self.secrets.get_secret(&name) {
+            Ok(token) => token.unwrap_or_default(),
+            Err(error) => {
+                eprintln!("Cannot read the stored API token: {}", error);
+                String::default()
             }
         }
-        self.config.installed_mods_md5.push(md5.clone());
-        write_config_to_json(Path::new(&self.path), &self.config);
+    }
+
+    fn get_backup_dir(&self) -> PathBuf {
+        backup::get_backup_dir(&self.profile)
+    }
+
+    fn get_manifest_path(&self) -> String {
+        get_config_dir(&self.path)
+            .join(get_manifest_file_name(&self.profile))
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    // empty when no password is stored or the secret store can't be read
+    fn get_password(&self) -> String {
+        match self.secrets.get_secret(&self.password_secret_name()) {
+            Ok(password) => password.unwrap_or_default(),
+            Err(error) => {
+                eprintln!("Cannot read the stored password: {}", error);
+                String::default()
+            }
+        }
+    }
+
+    fn get_profile(&self) -> &Profile {
+        &self.config.profiles[&self.profile]
+    }
+
+    fn get_profile_mut(&mut self) -> &mut Profile {
+        self.config.profiles.get_mut(&self.profile).unwrap()
+    }
+
+    fn get_profile_name(&self) -> &str {
+        &self.profile
+    }
+
+    fn get_profile_names(&self) -> Vec<String> {
+        self.config.profiles.keys().cloned().collect()
+    }
+
+    fn get_retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.config.retry_attempts,
+            initial_backoff: Duration::from_millis(self.config.retry_backoff_ms),
+            max_backoff: Duration::from_millis(self.config.retry_max_backoff_ms),
+            retryable_statuses: self.config.retry_http_statuses.clone(),
+        }
+    }
+
+    // the session cookies are secrets as well, whoever has them is logged in
+    fn get_stored_session(&self) -> Option<StoredSession> {
+        let session = match self.secrets.get_secret(&self.session_secret_name()) {
+            Ok(session) => session?,
+            Err(error) => {
+                eprintln!("Cannot read the stored session: {}", error);
+                return None;
+            }
+        };
+        serde_json::from_str(&session).ok()
+    }
+
+    fn get_unpack_limits(&self) -> UnpackLimits {
+        UnpackLimits {
+            max_size_in_bytes: self.config.max_unpacked_size_mb * 1024 * 1024,
+            max_file_count: self.config.max_unpacked_file_count,
+        }
+    }
+
+    fn get_warnings(&self) -> &Vec<String> {
+        &self.warnings
     }
 
     fn is_mod_installed(&self, md5: &String) -> bool {
-        for checksum in self.config.installed_mods_md5.iter() {
+        for checksum in self.get_profile().installed_mods_md5.iter() {
             if *md5 == *checksum {
                 return true;
             }
@@ -70,18 +617,361 @@ impl ConfigTrait for ConfigObject {
         false
     }
 
+    fn remove_installed_mod(&mut self, md5: &str) {
+        self.get_profile_mut()
+            .installed_mods_md5
+            .retain(|checksum| *checksum != *md5);
+        self.save();
+    }
+
+    // Switches to the profile with the given name and remembers it for the next
+    // run, returns true when the profile had to be created
+    fn select_profile(&mut self, name: &str) -> Result<bool> {
+        if !is_valid_profile_name(name) {
+            return Err(Error::Config(format!(
+                "Invalid profile name \"{}\": only letters, digits, - and _ are allowed",
+                name
+            )));
+        }
+        let created = !self.config.profiles.contains_key(name);
+        if created {
+            eprintln!("Creating profile {}", name);
+            self.config
+                .profiles
+                .insert(name.to_string(), Profile::default());
+        }
+        self.profile = name.to_string();
+        self.config.selected_profile = name.to_string();
+        self.save();
+        Ok(created)
+    }
+
     fn set_assetto_path(&mut self, path: String) {
-        self.config.assetto_path = path;
-        write_config_to_json(Path::new(&self.path), &self.config);
+        self.get_profile_mut().assetto_path = path;
+        self.save();
     }
 
+    // the password and session of the previous login aren't needed anymore
     fn set_login(&mut self, login: String) {
-        self.config.login = login;
-        write_config_to_json(Path::new(&self.path), &self.config);
+        if login != self.get_profile().login {
+            if let Err(error) = self.secrets.delete_secret(&self.password_secret_name()) {
+                eprintln!("Cannot remove the stored password: {}", error);
+            }
+            self.set_stored_session(None);
+        }
+        self.get_profile_mut().login = login;
+        self.save();
     }
 
     fn set_password(&mut self, password: String) {
-        self.config.password = password;
-        write_config_to_json(Path::new(&self.path), &self.config);
+        let name = self.password_secret_name();
+        if let Err(error) = self.secrets.set_secret(&name, &password) {
+            eprintln!(
+                "Cannot store the password in {}: {}",
+                self.secrets.get_name(),
+                error
+            );
+        }
+    }
+
+    fn set_server_url(&mut self, server_url: String) {
+        self.get_profile_mut().server_url = server_url;
+        self.save();
+    }
+
+    fn set_stored_session(&mut self, session: Option<&StoredSession>) {
+        let name = self.session_secret_name();
+        let result = match session {
+            Some(session) => self
+                .secrets
+                .set_secret(&name, &serde_json::to_string(session).unwrap()),
+            None => self.secrets.delete_secret(&name),
+        };
+        if let Err(error) = result {
+            eprintln!(
+                "Cannot store the session in {}: {}",
+                self.secrets.get_name(),
+                error
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secret_store::EncryptedFileStore;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_migrate_password() {
+        let dir = TempDir::new("config_test").unwrap();
+        let mut secrets =
+            EncryptedFileStore::new(&dir.path().join("secrets"), &dir.path().join("key"));
+        let mut config: Config = serde_json::from_str(
+            r#"{"assetto_path": "", "login": "user", "installed_mods_md5": [], "password": "hunter2"}"#,
+        )
+        .unwrap();
+
+        migrate_profiles(
+            &mut config,
+            &mut secrets,
+            dir.path(),
+            &dir.path().join("backups"),
+        );
+        migrate_password(&mut config, &mut secrets);
+        assert_eq!(
+            secrets.get_secret("password:default:user"),
+            Ok(Some("hunter2".to_string()))
+        );
+        assert!(!serde_json::to_string(&config).unwrap().contains("password"));
+    }
+
+    #[test]
+    fn test_migrate_profiles() {
+        let dir = TempDir::new("config_test").unwrap();
+        let mut secrets =
+            EncryptedFileStore::new(&dir.path().join("secrets"), &dir.path().join("key"));
+        secrets.set_secret("password:user", "hunter2").unwrap();
+        std::fs::write(dir.path().join("assetto_sync_manifest.json"), "{}").unwrap();
+        let backup_root = dir.path().join("backups");
+        backup::BackupSession::new(&backup_root, "").save().unwrap();
+        let mut config: Config = serde_json::from_str(
+            r#"{"assetto_path": "/games/ac", "login": "user", "installed_mods_md5": ["abc"],
+                "server_url": "https://sync.example.com", "download_concurrency": 4}"#,
+        )
+        .unwrap();
+
+        migrate_profiles(&mut config, &mut secrets, dir.path(), &backup_root);
+        assert_eq!(config.selected_profile, DEFAULT_PROFILE);
+        assert_eq!(
+            config.profiles[DEFAULT_PROFILE],
+            Profile {
+                assetto_path: "/games/ac".to_string(),
+                login: "user".to_string(),
+                installed_mods_md5: vec!["abc".to_string()],
+                server_url: "https://sync.example.com".to_string(),
+                api_token: String::default(),
+            }
+        );
+        assert_eq!(config.download_concurrency, 4);
+        assert_eq!(secrets.get_secret("password:user"), Ok(None));
+        assert_eq!(
+            secrets.get_secret("password:default:user"),
+            Ok(Some("hunter2".to_string()))
+        );
+        assert!(dir
+            .path()
+            .join("assetto_sync_manifest.default.json")
+            .exists());
+        let sessions = backup::list_sessions(&backup_root.join(DEFAULT_PROFILE));
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].assetto_path, "/games/ac");
+
+        // the flat settings are gone once they are in the profile
+        let json = serde_json::to_string(&config).unwrap();
+        let config: Config = serde_json::from_str(&json).unwrap();
+        assert!(config.login.is_none());
+        assert_eq!(config.profiles.len(), 1);
+    }
+
+    #[test]
+    fn test_migrate_config() {
+        let dir = TempDir::new("config_test").unwrap();
+        let mut secrets =
+            EncryptedFileStore::new(&dir.path().join("secrets"), &dir.path().join("key"));
+        let mut config: Config = serde_json::from_str(
+            r#"{"assetto_path": "", "login": "user", "installed_mods_md5": []}"#,
+        )
+        .unwrap();
+        assert_eq!(config.version, 0);
+
+        migrate_config(
+            &mut config,
+            &mut secrets,
+            dir.path(),
+            &dir.path().join("backups"),
+        );
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert_eq!(config.profiles[DEFAULT_PROFILE].login, "user");
+
+        // a current config is left alone
+        config.profiles.clear();
+        migrate_config(
+            &mut config,
+            &mut secrets,
+            dir.path(),
+            &dir.path().join("backups"),
+        );
+        assert!(config.profiles.is_empty());
+    }
+
+    #[test]
+    fn test_read_and_write_config() {
+        let dir = TempDir::new("config_test").unwrap();
+        let path = dir.path().join("config.json");
+        let path_str = path.to_str().unwrap();
+
+        let (config, warnings) = read_config(path_str);
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert!(warnings.is_empty());
+
+        write_config_to_json(&path, &config);
+        assert!(!dir.path().join("config.json.tmp").exists());
+        let (mut config, warnings) = read_config(path_str);
+        assert!(warnings.is_empty());
+
+        config.version = CONFIG_VERSION + 1;
+        write_config_to_json(&path, &config);
+        let (_, warnings) = read_config(path_str);
+        assert_eq!(warnings.len(), 1);
+    }
+
+    fn open_config(dir: &Path) -> ConfigObject {
+        let secrets = EncryptedFileStore::new(&dir.join("secrets"), &dir.join("key"));
+        ConfigObject::open(
+            dir.join("config.json").to_str().unwrap(),
+            Box::new(secrets),
+            &dir.join("backups"),
+        )
+    }
+
+    #[test]
+    fn test_config_backup() {
+        let dir = TempDir::new("config_test").unwrap();
+        let backup_path = dir.path().join("config.json.bak");
+        let read_backup = || std::fs::read_to_string(&backup_path).unwrap();
+
+        // a first run has nothing to back up, a run which changes nothing keeps the backup
+        open_config(dir.path());
+        assert!(!backup_path.exists());
+        let mut config = open_config(dir.path());
+        assert!(!backup_path.exists());
+
+        // the backup has the file as it was loaded, later writes of the run don't touch it
+        config.add_installed_mod(&"abc".to_string());
+        assert!(!read_backup().contains("abc"));
+        config.add_installed_mod(&"def".to_string());
+        assert!(!read_backup().contains("abc"));
+
+        let mut config = open_config(dir.path());
+        assert!(!read_backup().contains("abc"));
+        config.remove_installed_mod("abc");
+        assert!(read_backup().contains("abc"));
+    }
+
+    #[test]
+    fn test_password_is_not_left_in_copies() {
+        let dir = TempDir::new("config_test").unwrap();
+        let path = dir.path().join("config.json");
+        std::fs::write(
+            &path,
+            r#"{"assetto_path": "/games/ac", "login": "user", "installed_mods_md5": [], "password": "hunter2"}"#,
+        )
+        .unwrap();
+
+        let config = open_config(dir.path());
+        assert_eq!(config.get_password(), "hunter2");
+        assert!(!std::fs::read_to_string(&path).unwrap().contains("hunter2"));
+        let backup = std::fs::read_to_string(dir.path().join("config.json.bak")).unwrap();
+        assert!(backup.contains("/games/ac"));
+        assert!(!backup.contains("hunter2"));
+
+        // a config which is JSON but not a valid config is quarantined without it
+        std::fs::write(&path, r#"{"profiles": 1, "password": "hunter2"}"#).unwrap();
+        let (_, warnings) = read_config(path.to_str().unwrap());
+        assert_eq!(warnings.len(), 1);
+        for entry in std::fs::read_dir(dir.path()).unwrap() {
+            let entry = entry.unwrap();
+            if entry.file_name().to_string_lossy().contains("broken") {
+                assert!(!std::fs::read_to_string(entry.path())
+                    .unwrap()
+                    .contains("hunter2"));
+            }
+        }
+    }
+
+    #[test]
+    fn test_api_token_is_moved_to_secret_store() {
+        let dir = TempDir::new("config_test").unwrap();
+        let path = dir.path().join("config.json");
+        std::fs::write(
+            &path,
+            r#"{"version": 1, "selected_profile": "ci", "profiles": {
+                "ci": {"assetto_path": "/games/ac", "login": "", "installed_mods_md5": [], "api_token": "token1"},
+                "other": {"assetto_path": "", "login": "", "installed_mods_md5": []}}}"#,
+        )
+        .unwrap();
+
+        let mut config = open_config(dir.path());
+        assert_eq!(config.get_api_token(), "token1");
+        assert!(!std::fs::read_to_string(&path).unwrap().contains("token1"));
+        let backup = std::fs::read_to_string(dir.path().join("config.json.bak")).unwrap();
+        assert!(backup.contains("/games/ac"));
+        assert!(!backup.contains("token1"));
+
+        // the token belongs to its profile only
+        config.select_profile("other").unwrap();
+        assert_eq!(config.get_api_token(), "");
+        let mut config = open_config(dir.path());
+        config.select_profile("ci").unwrap();
+        assert_eq!(config.get_api_token(), "token1");
+    }
+
+    #[test]
+    fn test_newer_config_is_not_written() {
+        let dir = TempDir::new("config_test").unwrap();
+        let path = dir.path().join("config.json");
+        let content = format!(
+            r#"{{"version": {}, "selected_profile": "default", "profiles": {{}}, "future_setting": 1}}"#,
+            CONFIG_VERSION + 1
+        );
+        std::fs::write(&path, &content).unwrap();
+
+        let mut config = open_config(dir.path());
+        assert_eq!(config.get_warnings().len(), 1);
+        config.add_installed_mod(&"abc".to_string());
+        assert!(config.is_mod_installed(&"abc".to_string()));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), content);
+        assert!(!dir.path().join("config.json.bak").exists());
+    }
+
+    #[test]
+    fn test_newer_config_with_another_layout_is_kept() {
+        let dir = TempDir::new("config_test").unwrap();
+        let path = dir.path().join("config.json");
+        let content = format!(
+            r#"{{"version": {}, "profiles": [{{"name": "default"}}]}}"#,
+            CONFIG_VERSION + 1
+        );
+        std::fs::write(&path, &content).unwrap();
+
+        let mut config = open_config(dir.path());
+        assert_eq!(config.get_warnings().len(), 1);
+        assert_eq!(config.get_profile_name(), DEFAULT_PROFILE);
+        config.set_assetto_path("/games/ac".to_string());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), content);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_broken_config_is_quarantined() {
+        let dir = TempDir::new("config_test").unwrap();
+        let path = dir.path().join("config.json");
+        std::fs::write(&path, r#"{"version": 1, "profiles": {"#).unwrap();
+
+        let (config, warnings) = read_config(path.to_str().unwrap());
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert!(config.profiles.is_empty());
+        assert_eq!(warnings.len(), 1);
+        assert!(!path.exists());
+
+        let quarantined: Vec<String> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(quarantined.len(), 1);
+        assert!(quarantined[0].starts_with("config.json.broken-"));
+        assert!(warnings[0].contains(&quarantined[0]));
     }
 }
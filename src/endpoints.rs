@@ -0,0 +1,116 @@
+use reqwest::Url;
+
+use crate::error::{Error, Result};
+
+pub const DEFAULT_SERVER_URL: &str = "http://127.0.0.1:8080";
+
+#[derive(Clone, Debug)]
+pub struct Endpoints {
+    base_url: Url,
+}
+
+impl Endpoints {
+    pub fn new(server_url: &str) -> Result<Endpoints> {
+        let base_url = Url::parse(server_url.trim());
+        if let Err(error) = base_url {
+            return Err(Error::Config(format!(
+                "Invalid server URL \"{}\": {}",
+                server_url, error
+            )));
+        }
+        let mut base_url = base_url.unwrap();
+
+        if base_url.scheme() != "http" && base_url.scheme() != "https" {
+            return Err(Error::Config(format!(
+                "Invalid server URL \"{}\": only http and https are supported",
+                server_url
+            )));
+        }
+        if base_url.cannot_be_a_base() || base_url.host_str().is_none() {
+            return Err(Error::Config(format!(
+                "Invalid server URL \"{}\": missing host",
+                server_url
+            )));
+        }
+        if base_url.query().is_some() || base_url.fragment().is_some() {
+            return Err(Error::Config(format!(
+                "Invalid server URL \"{}\": query and fragment are not allowed",
+                server_url
+            )));
+        }
+
+        // Url::join replaces the last path segment unless the path ends with a slash,
+        // so servers hosted under a prefix (e.g. /assetto_sync) need it appended
+        if !base_url.path().ends_with('/') {
+            let path = format!("{}/", base_url.path());
+            base_url.set_path(&path);
+        }
+
+        Ok(Endpoints { base_url })
+    }
+
+    pub fn base_url(&self) -> &Url {
+        &self.base_url
+    }
+
+    pub fn login(&self) -> Url {
+        self.join("login")
+    }
+
+    pub fn mods_json(&self) -> Url {
+        self.join("mods.json")
+    }
+
+    pub fn mod_download(&self, md5_hash: &str) -> Url {
+        let mut url = self.join("mod_management/download");
+        url.query_pairs_mut().append_pair("hash", md5_hash);
+        url
+    }
+
+    fn join(&self, path: &str) -> Url {
+        self.base_url.join(path).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_server_url() {
+        let endpoints = Endpoints::new(DEFAULT_SERVER_URL).unwrap();
+
+        assert_eq!(endpoints.login().as_str(), "http://127.0.0.1:8080/login");
+        assert_eq!(
+            endpoints.mods_json().as_str(),
+            "http://127.0.0.1:8080/mods.json"
+        );
+        assert_eq!(
+            endpoints.mod_download("abcdef").as_str(),
+            "http://127.0.0.1:8080/mod_management/download?hash=abcdef"
+        );
+    }
+
+    #[test]
+    fn test_server_url_with_path_prefix() {
+        let endpoints = Endpoints::new("https://example.com/assetto_sync").unwrap();
+
+        assert_eq!(
+            endpoints.login().as_str(),
+            "https://example.com/assetto_sync/login"
+        );
+        assert_eq!(
+            endpoints.mods_json().as_str(),
+            "https://example.com/assetto_sync/mods.json"
+        );
+    }
+
+    #[test]
+    fn test_malformed_server_urls() {
+        assert!(Endpoints::new("").is_err());
+        assert!(Endpoints::new("127.0.0.1:8080").is_err());
+        assert!(Endpoints::new("ftp://example.com").is_err());
+        assert!(Endpoints::new("http://example.com/?a=b").is_err());
+        assert!(Endpoints::new("mailto:someone@example.com").is_err());
+    }
+}
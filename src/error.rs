@@ -0,0 +1,128 @@
+use std::fmt;
+
+use crate::retry;
+use crate::JsonModTemplate;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Error {
+    Network(String),
+    Http { status: u16, url: String },
+    Auth(String),
+    SessionExpired,
+    Checksum { expected: String, actual: String },
+    Size { expected: u64, actual: u64 },
+    Unpack(String),
+    LayoutDetection(String),
+    Filesystem(String),
+    Config(String),
+    Cancelled,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::Network(_) => "network",
+            Error::Http { .. } => "http",
+            Error::Auth(_) => "auth",
+            Error::SessionExpired => "session_expired",
+            Error::Checksum { .. } => "checksum",
+            Error::Size { .. } => "size",
+            Error::Unpack(_) => "unpack",
+            Error::LayoutDetection(_) => "layout_detection",
+            Error::Filesystem(_) => "filesystem",
+            Error::Config(_) => "config",
+            Error::Cancelled => "cancelled",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Network(message) => write!(f, "network error: {}", message),
+            Error::Http { status, url } => write!(f, "HTTP status {} from {}", status, url),
+            Error::Auth(message) => write!(f, "authentication error: {}", message),
+            Error::SessionExpired => write!(f, "session expired, logging in again failed"),
+            Error::Checksum { expected, actual } => write!(
+                f,
+                "checksum mismatch (expected: {}, actual: {})",
+                expected, actual
+            ),
+            Error::Size { expected, actual } => write!(
+                f,
+                "size mismatch (expected: {}, actual: {})",
+                expected, actual
+            ),
+            Error::Unpack(message) => write!(f, "unpack error: {}", message),
+            Error::LayoutDetection(message) => write!(f, "cannot detect mod layout: {}", message),
+            // filesystem messages already name the path and operation
+            Error::Filesystem(message) => write!(f, "{}", message),
+            Error::Config(message) => write!(f, "{}", message),
+            Error::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<reqwest::Error> for Error {
+    fn from(error: reqwest::Error) -> Error {
+        Error::Network(error.to_string())
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Error {
+        Error::Filesystem(error.to_string())
+    }
+}
+
+impl From<walkdir::Error> for Error {
+    fn from(error: walkdir::Error) -> Error {
+        Error::Filesystem(error.to_string())
+    }
+}
+
+impl From<compress_tools::Error> for Error {
+    fn from(error: compress_tools::Error) -> Error {
+        Error::Unpack(error.to_string())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModError {
+    pub checksum_md5: String,
+    pub filename: String,
+    pub error: Error,
+    pub attempts: u32,
+}
+
+impl ModError {
+    pub fn new(task: &JsonModTemplate, error: Error) -> ModError {
+        ModError {
+            checksum_md5: task.checksum_md5.clone(),
+            filename: task.filename.clone(),
+            error,
+            attempts: 1,
+        }
+    }
+
+    pub fn with_attempts(mut self, attempts: u32) -> ModError {
+        self.attempts = attempts;
+        self
+    }
+}
+
+impl fmt::Display for ModError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Mod {}, {}{}",
+            self.filename,
+            self.error,
+            retry::describe_attempts(self.attempts)
+        )
+    }
+}
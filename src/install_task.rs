@@ -1,7 +1,7 @@
 use wildmatch::WildMatch;
 
 use crate::common::FsEntry;
-use std::collections::HashSet;
+use crate::error::{Error, Result};
 use std::path::Path;
 
 #[derive(Debug, PartialEq)]
@@ -57,7 +57,8 @@ pub struct Mod {
 }
 
 pub fn find_mods(entry_list: &Vec<FsEntry>) -> Vec<Mod> {
-    let mut mod_dirs: HashSet<String> = HashSet::new();
+    // keep archive order so the install tasks are deterministic
+    let mut mod_dirs: Vec<String> = vec![];
 
     let kn5_model_list: Vec<&FsEntry> = entry_list
         .iter()
@@ -70,7 +71,9 @@ pub fn find_mods(entry_list: &Vec<FsEntry>) -> Vec<Mod> {
 
     for model in kn5_model_list {
         let dir_path = get_directory_name_from_entry(model);
-        mod_dirs.insert(dir_path);
+        if !mod_dirs.contains(&dir_path) {
+            mod_dirs.push(dir_path);
+        }
     }
 
     let mut mods = vec![];
@@ -90,7 +93,7 @@ fn extension_dir_is_mod_dir(entry_list: &Vec<FsEntry>, entry: &FsEntry) -> bool
     determine_content_type(entry_list, dir.to_str().unwrap()) != ContentType::Unknown
 }
 
-pub fn determine_install_tasks(entry_list: &Vec<FsEntry>) -> Result<Vec<InstallTask>, &str> {
+pub fn determine_install_tasks(entry_list: &Vec<FsEntry>) -> Result<Vec<InstallTask>> {
     let content_dirs: Vec<&FsEntry> = entry_list
         .iter()
         .filter(|&p| !p.is_file && Path::new(&p.path).file_name().unwrap() == "content")
@@ -111,7 +114,9 @@ pub fn determine_install_tasks(entry_list: &Vec<FsEntry>) -> Result<Vec<InstallT
     let extension_dir_count = extension_dirs.len();
 
     if content_dir_count > 1 || extension_dir_count > 1 {
-        return Err("Multiple content or extension dirs found");
+        return Err(Error::LayoutDetection(
+            "multiple content or extension dirs found".to_string(),
+        ));
     }
 
     let mut ret: Vec<InstallTask> = vec![];
@@ -148,10 +153,19 @@ pub fn determine_install_tasks(entry_list: &Vec<FsEntry>) -> Result<Vec<InstallT
                 source_path: ac_mod.path,
                 target_path: "content/tracks".to_string(),
             }),
-            ContentType::Unknown => println!("Sum ting wong"),
+            ContentType::Unknown => eprintln!(
+                "Skipping {}, not recognized as a car or a track",
+                ac_mod.path
+            ),
         }
     }
 
+    if ret.is_empty() {
+        return Err(Error::LayoutDetection(
+            "no content dir, car or track found in archive".to_string(),
+        ));
+    }
+
     Ok(ret)
 }
 
@@ -264,6 +278,27 @@ mod tests {
         assert!(tasks.is_err());
     }
 
+    #[test]
+    fn test_unrecognized_layout() {
+        let simple_mod_entries: Vec<FsEntry> = vec![
+            FsEntry {
+                path: "/tmp/unpacked/readme.txt".to_string(),
+                is_file: true,
+            },
+            FsEntry {
+                path: "/tmp/unpacked/some_dir".to_string(),
+                is_file: false,
+            },
+        ];
+
+        let tasks = determine_install_tasks(&simple_mod_entries);
+
+        match tasks {
+            Err(Error::LayoutDetection(_)) => {}
+            _ => panic!("expected layout detection error"),
+        }
+    }
+
     #[test]
     fn test_one_car_dir() {
         let simple_mod_entries: Vec<FsEntry> = vec![
@@ -0,0 +1,320 @@
+use std::path::{Path, PathBuf};
+
+use reqwest::{header::RANGE, Client, StatusCode, Url};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncReadExt, AsyncWriteExt},
+};
+
+use crate::error::{Error, Result};
+use crate::server;
+use crate::JsonModTemplate;
+
+pub struct DownloadedArchive {
+    pub size_in_bytes: u64,
+    pub checksum_md5: String,
+}
+
+pub fn get_download_cache_dir() -> PathBuf {
+    dirs::cache_dir()
+        .unwrap_or(std::env::temp_dir())
+        .join("assetto_sync_client")
+        .join("downloads")
+}
+
+// Archives are kept as <cache>/<md5>/<filename> so an interrupted download of the same
+// mod can be resumed on the next run, the original filename is kept for unpack_archive.
+// The checksum comes from the server, anything but an MD5 could point the directory,
+// which is removed with the archive, outside of the cache
+pub fn get_cached_archive_path(cache_dir: &Path, task: &JsonModTemplate) -> Result<PathBuf> {
+    let checksum = &task.checksum_md5;
+    if checksum.len() != 32 || !checksum.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(Error::Network(format!(
+            "the server sent an invalid checksum \"{}\" for {}",
+            checksum, task.filename
+        )));
+    }
+    let filename = Path::new(&task.filename)
+        .file_name()
+        .map(|name| name.to_os_string())
+        .unwrap_or(checksum.clone().into());
+    Ok(cache_dir.join(checksum).join(filename))
+}
+
+pub fn remove_cached_archive(archive_path: &Path) {
+    if let Some(dir) = archive_path.parent() {
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
+
+async fn hash_partial_download(archive_path: &Path, md5_context: &mut md5::Context) -> Result<u64> {
+    let file = File::open(archive_path).await;
+    if file.is_err() {
+        return Ok(0);
+    }
+    let mut file = file.unwrap();
+
+    let mut buffer = vec![0u8; 65536];
+    let mut size: u64 = 0;
+    loop {
+        let read = file.read(&mut buffer).await;
+        if let Err(error) = read {
+            return Err(Error::Filesystem(format!(
+                "cannot read {}: {}",
+                archive_path.display(),
+                error
+            )));
+        }
+        let read = read.unwrap();
+        if read == 0 {
+            break;
+        }
+        md5_context.consume(&buffer[..read]);
+        size += read as u64;
+    }
+    Ok(size)
+}
+
+pub async fn download_archive<F: FnMut(u64), C: Fn() -> bool>(
+    client: &Client,
+    link: Url,
+    archive_path: &Path,
+    expected_size: u64,
+    mut on_progress: F,
+    is_cancelled: C,
+) -> Result<DownloadedArchive> {
+    if let Some(dir) = archive_path.parent() {
+        if let Err(error) = tokio::fs::create_dir_all(dir).await {
+            return Err(Error::Filesystem(format!(
+                "cannot create {}: {}",
+                dir.display(),
+                error
+            )));
+        }
+    }
+
+    let mut md5_context = md5::Context::new();
+    let mut downloaded_bytes = hash_partial_download(archive_path, &mut md5_context).await?;
+    if downloaded_bytes > expected_size {
+        md5_context = md5::Context::new();
+        downloaded_bytes = 0;
+    }
+    if downloaded_bytes > 0 && downloaded_bytes == expected_size {
+        on_progress(downloaded_bytes);
+        return Ok(DownloadedArchive {
+            size_in_bytes: downloaded_bytes,
+            checksum_md5: format!("{:x}", md5_context.compute()),
+        });
+    }
+
+    let mut request = client.get(link);
+    if downloaded_bytes > 0 {
+        eprintln!(
+            "Resuming {} from byte {}",
+            archive_path.display(),
+            downloaded_bytes
+        );
+        request = request.header(RANGE, format!("bytes={}-", downloaded_bytes));
+    }
+    let response = request.send().await;
+    if let Err(error) = response {
+        return Err(Error::from(error));
+    }
+    let mut response = server::check_response(response.unwrap())?;
+
+    // servers which ignore Range reply with 200 and the whole file
+    let resumed = downloaded_bytes > 0 && response.status() == StatusCode::PARTIAL_CONTENT;
+    if !resumed {
+        md5_context = md5::Context::new();
+        downloaded_bytes = 0;
+    }
+
+    let file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(resumed)
+        .truncate(!resumed)
+        .open(archive_path)
+        .await;
+    if let Err(error) = file {
+        return Err(Error::Filesystem(format!(
+            "cannot create {}: {}",
+            archive_path.display(),
+            error
+        )));
+    }
+    let mut file = file.unwrap();
+
+    on_progress(downloaded_bytes);
+    loop {
+        if is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        let chunk = response.chunk().await;
+        let chunk = match chunk {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(error) => return Err(Error::from(error)),
+        };
+
+        if let Err(error) = file.write_all(&chunk).await {
+            return Err(Error::Filesystem(format!(
+                "cannot write {}: {}",
+                archive_path.display(),
+                error
+            )));
+        }
+        md5_context.consume(&chunk);
+        downloaded_bytes += chunk.len() as u64;
+        on_progress(downloaded_bytes);
+    }
+
+    if let Err(error) = file.flush().await {
+        return Err(Error::Filesystem(format!(
+            "cannot write {}: {}",
+            archive_path.display(),
+            error
+        )));
+    }
+    Ok(DownloadedArchive {
+        size_in_bytes: downloaded_bytes,
+        checksum_md5: format!("{:x}", md5_context.compute()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::read_request;
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+    use tempdir::TempDir;
+    use tokio::net::TcpListener;
+
+    fn archive_content() -> Vec<u8> {
+        (0..65536).map(|i| (i % 251) as u8).collect()
+    }
+
+    // Sends the archive for any request and records the requests, the connection of
+    // the first response breaks after cut_at bytes. A Range request is answered with
+    // the rest of the archive when support_range is set and with all of it otherwise
+    async fn serve_archive(
+        content: Vec<u8>,
+        cut_at: usize,
+        support_range: bool,
+    ) -> (Url, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(vec![]));
+        let received = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let request = read_request(&mut stream).await.to_lowercase();
+                let start = request
+                    .lines()
+                    .find_map(|line| line.strip_prefix("range: bytes="))
+                    .and_then(|range| range.trim_end_matches('-').parse::<usize>().ok());
+                let first = received.lock().unwrap().is_empty();
+                received.lock().unwrap().push(request);
+
+                let (status, body) = match start {
+                    Some(start) if support_range => (206, &content[start..]),
+                    _ => (200, &content[..]),
+                };
+                let header = format!(
+                    "HTTP/1.1 {} Test\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    body.len()
+                );
+                let _ = stream.write_all(header.as_bytes()).await;
+                if first {
+                    let _ = stream.write_all(&body[..cut_at]).await;
+                    let _ = stream.flush().await;
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    continue;
+                }
+                let _ = stream.write_all(body).await;
+            }
+        });
+        let link = Url::parse(&format!("http://{}/download", address)).unwrap();
+        (link, requests)
+    }
+
+    // the first attempt is interrupted, returns the size of the partial download and
+    // the result of the second attempt
+    async fn download_twice(
+        link: Url,
+        archive_path: &Path,
+        content: &[u8],
+    ) -> (usize, DownloadedArchive) {
+        let client = Client::new();
+        let size = content.len() as u64;
+        let result =
+            download_archive(&client, link.clone(), archive_path, size, |_| {}, || false).await;
+        assert!(matches!(result, Err(Error::Network(_))));
+
+        let partial = std::fs::read(archive_path).unwrap();
+        assert!(!partial.is_empty());
+        assert!(content.starts_with(&partial));
+
+        let result = download_archive(&client, link, archive_path, size, |_| {}, || false).await;
+        (partial.len(), result.unwrap())
+    }
+
+    #[test]
+    fn test_invalid_checksum_is_rejected() {
+        let cache_dir = Path::new("cache");
+        let mut task = crate::test_utils::task("car.zip", b"car");
+        let archive_path = get_cached_archive_path(cache_dir, &task).unwrap();
+        assert_eq!(
+            archive_path,
+            cache_dir.join(&task.checksum_md5).join("car.zip")
+        );
+
+        for checksum in ["", "..", "/", "/tmp", "0123456789abcdef0123456789abcde/"] {
+            task.checksum_md5 = checksum.to_string();
+            assert!(matches!(
+                get_cached_archive_path(cache_dir, &task),
+                Err(Error::Network(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_interrupted_download_is_resumed() {
+        let dir = TempDir::new("download_test").unwrap();
+        let archive_path = dir.path().join("md5").join("car.zip");
+        let content = archive_content();
+        let (link, requests) = serve_archive(content.clone(), 16384, true).await;
+
+        let (partial_size, archive) = download_twice(link, &archive_path, &content).await;
+        assert!(requests.lock().unwrap()[1].contains(&format!("range: bytes={}-", partial_size)));
+        assert_eq!(archive.size_in_bytes, content.len() as u64);
+        assert_eq!(
+            archive.checksum_md5,
+            format!("{:x}", md5::compute(&content))
+        );
+        assert_eq!(std::fs::read(&archive_path).unwrap(), content);
+    }
+
+    #[tokio::test]
+    async fn test_ignored_range_restarts_download() {
+        let dir = TempDir::new("download_test").unwrap();
+        let archive_path = dir.path().join("md5").join("car.zip");
+        let content = archive_content();
+        let (link, requests) = serve_archive(content.clone(), 16384, false).await;
+
+        // the server replies with 200 and the whole archive, it isn't appended
+        let (partial_size, archive) = download_twice(link, &archive_path, &content).await;
+        assert!(requests.lock().unwrap()[1].contains(&format!("range: bytes={}-", partial_size)));
+        assert_eq!(archive.size_in_bytes, content.len() as u64);
+        assert_eq!(
+            archive.checksum_md5,
+            format!("{:x}", md5::compute(&content))
+        );
+        assert_eq!(std::fs::read(&archive_path).unwrap(), content);
+    }
+}
@@ -0,0 +1,510 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::sync::mpsc;
+
+use crate::error::{Error, ModError};
+use crate::JsonModTemplate;
+
+// transfer speed is averaged over the last few seconds
+const SPEED_WINDOW: Duration = Duration::from_secs(5);
+const SPEED_SAMPLE_INTERVAL: Duration = Duration::from_millis(250);
+
+// index is the position of the mod in the task list the thread was created with
+#[derive(Clone, Debug)]
+pub enum InstallEvent {
+    ModStarted {
+        index: usize,
+    },
+    Downloading {
+        index: usize,
+        downloaded_bytes: u64,
+        total_bytes: u64,
+    },
+    Downloaded {
+        index: usize,
+    },
+    Unpacking {
+        index: usize,
+    },
+    InstallingTask {
+        index: usize,
+        source_path: String,
+        target_path: String,
+    },
+    ModPlanned {
+        index: usize,
+    },
+    ModInstalled {
+        index: usize,
+    },
+    Retrying {
+        index: usize,
+        attempt: u32,
+        max_attempts: u32,
+        backoff: Duration,
+        error: Error,
+    },
+    ModFailed {
+        index: usize,
+        error: ModError,
+    },
+    ModCancelled {
+        index: usize,
+    },
+    Finished,
+}
+
+#[derive(Clone)]
+pub struct InstallEventSender {
+    subscribers: Arc<Mutex<Vec<mpsc::UnboundedSender<InstallEvent>>>>,
+}
+
+impl InstallEventSender {
+    pub fn new() -> InstallEventSender {
+        InstallEventSender {
+            subscribers: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    pub fn subscribe(&self) -> mpsc::UnboundedReceiver<InstallEvent> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    // subscribers which dropped their receiver are forgotten
+    pub fn send(&self, event: InstallEvent) {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ModStatus {
+    Queued,
+    Downloading {
+        downloaded_bytes: u64,
+        total_bytes: u64,
+    },
+    Downloaded,
+    Unpacking,
+    Installing {
+        source_path: String,
+        target_path: String,
+    },
+    Installed,
+    Planned,
+    Failed(Error),
+    Cancelled,
+}
+
+impl ModStatus {
+    pub fn describe(&self) -> String {
+        match self {
+            ModStatus::Queued => "Queued".to_string(),
+            ModStatus::Downloading {
+                downloaded_bytes,
+                total_bytes,
+            } => format!(
+                "Downloading, {} / {}",
+                format_size(*downloaded_bytes),
+                format_size(*total_bytes)
+            ),
+            ModStatus::Downloaded => "Downloaded".to_string(),
+            ModStatus::Unpacking => "Unpacking".to_string(),
+            ModStatus::Installing {
+                source_path,
+                target_path,
+            } => format!("Installing {} -> {}", source_path, target_path),
+            ModStatus::Installed => "Installed".to_string(),
+            ModStatus::Planned => "Planned".to_string(),
+            ModStatus::Failed(error) => format!("Failed, {}", error),
+            ModStatus::Cancelled => "Cancelled".to_string(),
+        }
+    }
+}
+
+pub fn format_size(bytes: u64) -> String {
+    let megabytes = bytes as f64 / 1024.0 / 1024.0;
+    match megabytes >= 1024.0 {
+        true => format!("{:.2} GB", megabytes / 1024.0),
+        false => format!("{:.1} MB", megabytes),
+    }
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match seconds {
+        0..=59 => format!("{}s", seconds),
+        60..=3599 => format!("{}m {:02}s", seconds / 60, seconds % 60),
+        _ => format!("{}h {:02}m", seconds / 3600, seconds % 3600 / 60),
+    }
+}
+
+// Folds the event stream into the state of every mod, renderers keep one of these
+pub struct InstallProgress {
+    filenames: Vec<String>,
+    sizes: Vec<u64>,
+    mod_status: Vec<ModStatus>,
+    is_finished: bool,
+    last_downloaded_bytes: Vec<Option<u64>>,
+    transferred_bytes: u64,
+    speed_samples: VecDeque<(Instant, u64)>,
+}
+
+impl InstallProgress {
+    pub fn new(task_list: &[JsonModTemplate]) -> InstallProgress {
+        InstallProgress {
+            filenames: task_list.iter().map(|task| task.filename.clone()).collect(),
+            sizes: task_list.iter().map(|task| task.size_in_bytes).collect(),
+            mod_status: vec![ModStatus::Queued; task_list.len()],
+            is_finished: false,
+            last_downloaded_bytes: vec![None; task_list.len()],
+            transferred_bytes: 0,
+            speed_samples: VecDeque::new(),
+        }
+    }
+
+    pub fn apply(&mut self, event: &InstallEvent) {
+        self.apply_at(event, Instant::now());
+    }
+
+    fn apply_at(&mut self, event: &InstallEvent, now: Instant) {
+        match event {
+            InstallEvent::ModStarted { index } => {
+                self.mod_status[*index] = ModStatus::Downloading {
+                    downloaded_bytes: 0,
+                    total_bytes: self.sizes[*index],
+                }
+            }
+            InstallEvent::Downloading {
+                index,
+                downloaded_bytes,
+                total_bytes,
+            } => {
+                self.mod_status[*index] = ModStatus::Downloading {
+                    downloaded_bytes: *downloaded_bytes,
+                    total_bytes: *total_bytes,
+                };
+                // the first report of a resumed download is what was already on disk
+                if let Some(last_downloaded_bytes) = self.last_downloaded_bytes[*index] {
+                    self.transferred_bytes +=
+                        downloaded_bytes.saturating_sub(last_downloaded_bytes);
+                }
+                self.last_downloaded_bytes[*index] = Some(*downloaded_bytes);
+                self.record_speed_sample(now);
+            }
+            InstallEvent::Downloaded { index } => self.mod_status[*index] = ModStatus::Downloaded,
+            InstallEvent::Unpacking { index } => self.mod_status[*index] = ModStatus::Unpacking,
+            InstallEvent::InstallingTask {
+                index,
+                source_path,
+                target_path,
+            } => {
+                self.mod_status[*index] = ModStatus::Installing {
+                    source_path: source_path.clone(),
+                    target_path: target_path.clone(),
+                }
+            }
+            // the next attempt may start from the beginning when the server ignores
+            // Range, its first report is taken as the new baseline
+            InstallEvent::Retrying { index, .. } => self.last_downloaded_bytes[*index] = None,
+            InstallEvent::ModPlanned { index } => self.mod_status[*index] = ModStatus::Planned,
+            InstallEvent::ModInstalled { index } => self.mod_status[*index] = ModStatus::Installed,
+            InstallEvent::ModFailed { index, error } => {
+                self.mod_status[*index] = ModStatus::Failed(error.error.clone())
+            }
+            InstallEvent::ModCancelled { index } => self.mod_status[*index] = ModStatus::Cancelled,
+            InstallEvent::Finished => self.is_finished = true,
+        }
+    }
+
+    fn record_speed_sample(&mut self, now: Instant) {
+        if let Some((time, _)) = self.speed_samples.back() {
+            if now.duration_since(*time) < SPEED_SAMPLE_INTERVAL {
+                return;
+            }
+        }
+        self.speed_samples.push_back((now, self.transferred_bytes));
+        while let Some((time, _)) = self.speed_samples.front() {
+            if now.duration_since(*time) <= SPEED_WINDOW {
+                break;
+            }
+            self.speed_samples.pop_front();
+        }
+    }
+
+    // bytes per second, None until there is enough data or when the transfer stalled
+    pub fn get_speed(&self, now: Instant) -> Option<f64> {
+        let (time, bytes) = self
+            .speed_samples
+            .iter()
+            .find(|(time, _)| now.duration_since(*time) <= SPEED_WINDOW)?;
+        let elapsed = now.duration_since(*time).as_secs_f64();
+        if elapsed < 1.0 {
+            return None;
+        }
+        Some((self.transferred_bytes - bytes) as f64 / elapsed)
+    }
+
+    pub fn get_eta(&self, now: Instant) -> Option<Duration> {
+        let speed = self.get_speed(now)?;
+        if speed <= 0.0 {
+            return None;
+        }
+        let remaining_bytes = self.get_total_bytes() - self.get_processed_bytes();
+        Some(Duration::from_secs_f64(remaining_bytes as f64 / speed))
+    }
+
+    pub fn get_total_bytes(&self) -> u64 {
+        self.sizes.iter().sum()
+    }
+
+    // mods which are downloaded, installed or failed count with their full size
+    pub fn get_processed_bytes(&self) -> u64 {
+        (0..self.sizes.len())
+            .map(|index| self.get_mod_processed_bytes(index))
+            .sum()
+    }
+
+    fn get_mod_processed_bytes(&self, index: usize) -> u64 {
+        match &self.mod_status[index] {
+            ModStatus::Queued => 0,
+            ModStatus::Downloading {
+                downloaded_bytes, ..
+            } => std::cmp::min(*downloaded_bytes, self.sizes[index]),
+            _ => self.sizes[index],
+        }
+    }
+
+    pub fn get_fraction(&self) -> f64 {
+        match self.get_total_bytes() {
+            0 => self.get_done_count() as f64 / std::cmp::max(self.sizes.len(), 1) as f64,
+            total_bytes => self.get_processed_bytes() as f64 / total_bytes as f64,
+        }
+    }
+
+    pub fn get_mod_fraction(&self, index: usize) -> f64 {
+        match (&self.mod_status[index], self.sizes[index]) {
+            (ModStatus::Queued, _) => 0.0,
+            (ModStatus::Downloading { .. }, 0) => 0.0,
+            (_, 0) => 1.0,
+            (_, size) => self.get_mod_processed_bytes(index) as f64 / size as f64,
+        }
+    }
+
+    fn get_done_count(&self) -> usize {
+        self.mod_status
+            .iter()
+            .filter(|status| {
+                matches!(
+                    status,
+                    ModStatus::Installed
+                        | ModStatus::Planned
+                        | ModStatus::Failed(_)
+                        | ModStatus::Cancelled
+                )
+            })
+            .count()
+    }
+
+    pub fn describe_transfer(&self, now: Instant) -> String {
+        let mut line = format!(
+            "{}/{} mods processed, {} / {}",
+            self.get_done_count(),
+            self.mod_status.len(),
+            format_size(self.get_processed_bytes()),
+            format_size(self.get_total_bytes())
+        );
+        if let Some(speed) = self.get_speed(now) {
+            line += format!(", {}/s", format_size(speed as u64)).as_str();
+        }
+        if let Some(eta) = self.get_eta(now) {
+            line += format!(", {} left", format_duration(eta)).as_str();
+        }
+        line
+    }
+
+    // a line for the log of what the worker did, progress updates are left out
+    pub fn describe_event(&self, event: &InstallEvent) -> Option<String> {
+        match event {
+            InstallEvent::ModStarted { index } => {
+                Some(format!("Downloading {}", self.filenames[*index]))
+            }
+            InstallEvent::Downloading { .. } => None,
+            InstallEvent::Downloaded { index } => {
+                Some(format!("Downloaded {}", self.filenames[*index]))
+            }
+            InstallEvent::Unpacking { index } => {
+                Some(format!("Unpacking {}", self.filenames[*index]))
+            }
+            InstallEvent::InstallingTask {
+                index,
+                source_path,
+                target_path,
+            } => Some(format!(
+                "Installing {}, {} -> {}",
+                self.filenames[*index], source_path, target_path
+            )),
+            InstallEvent::ModPlanned { index } => {
+                Some(format!("Planned {}", self.filenames[*index]))
+            }
+            InstallEvent::ModInstalled { index } => {
+                Some(format!("Installed {}", self.filenames[*index]))
+            }
+            InstallEvent::Retrying {
+                index,
+                attempt,
+                max_attempts,
+                backoff,
+                error,
+            } => Some(format!(
+                "Downloading {} failed ({}), attempt {}/{}, retrying in {}",
+                self.filenames[*index],
+                error,
+                attempt,
+                max_attempts,
+                format_duration(*backoff)
+            )),
+            InstallEvent::ModFailed { error, .. } => Some(error.to_string()),
+            InstallEvent::ModCancelled { index } => {
+                Some(format!("Cancelled {}", self.filenames[*index]))
+            }
+            InstallEvent::Finished => Some("Finished".to_string()),
+        }
+    }
+
+    pub fn get_mod_status(&self) -> Vec<(&str, &ModStatus)> {
+        self.filenames
+            .iter()
+            .map(|filename| filename.as_str())
+            .zip(self.mod_status.iter())
+            .collect()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.is_finished
+    }
+
+    pub fn describe(&self) -> String {
+        if self.is_finished {
+            return "Finished".to_string();
+        }
+
+        let done_count = self.get_done_count();
+
+        let mut lines = vec![];
+        for (filename, status) in self.get_mod_status() {
+            match status {
+                ModStatus::Installing {
+                    source_path,
+                    target_path,
+                } => lines.push(format!(
+                    "Installing mod {}, {} -> {}",
+                    filename, source_path, target_path
+                )),
+                ModStatus::Unpacking => lines.push(format!("Unpacking mod {}", filename)),
+                ModStatus::Downloading {
+                    downloaded_bytes,
+                    total_bytes,
+                } => lines.push(format!(
+                    "Downloading mod {}, {}M / {}M",
+                    filename,
+                    downloaded_bytes / 1024 / 1024,
+                    total_bytes / 1024 / 1024
+                )),
+                _ => {}
+            }
+        }
+        lines.push(format!(
+            "{}/{} mods processed",
+            done_count,
+            self.mod_status.len()
+        ));
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::task;
+
+    #[tokio::test]
+    async fn test_progress_follows_events() {
+        let content = vec![0u8; 1024 * 1024];
+        let task_list = vec![task("car.zip", &content), task("track.7z", &content)];
+        let events = InstallEventSender::new();
+        let mut receiver = events.subscribe();
+
+        events.send(InstallEvent::ModStarted { index: 0 });
+        events.send(InstallEvent::Downloading {
+            index: 0,
+            downloaded_bytes: 1024 * 1024,
+            total_bytes: 2 * 1024 * 1024,
+        });
+        events.send(InstallEvent::ModFailed {
+            index: 1,
+            error: ModError::new(&task_list[1], Error::Network("timeout".to_string())),
+        });
+
+        let mut progress = InstallProgress::new(&task_list);
+        for _ in 0..3 {
+            progress.apply(&receiver.recv().await.unwrap());
+        }
+        assert_eq!(
+            progress.describe(),
+            "Downloading mod car.zip, 1M / 2M\n1/2 mods processed"
+        );
+
+        events.send(InstallEvent::InstallingTask {
+            index: 0,
+            source_path: "content".to_string(),
+            target_path: "content".to_string(),
+        });
+        events.send(InstallEvent::ModInstalled { index: 0 });
+        events.send(InstallEvent::Finished);
+        drop(events);
+        while let Some(event) = receiver.recv().await {
+            progress.apply(&event);
+        }
+        assert_eq!(progress.get_mod_status()[0].1, &ModStatus::Installed);
+        assert!(progress.is_finished());
+    }
+
+    #[test]
+    fn test_speed_and_eta() {
+        let content = vec![0u8; 1024 * 1024];
+        let task_list = vec![task("car.zip", &content), task("track.7z", &content)];
+        let mut progress = InstallProgress::new(&task_list);
+        let start = Instant::now();
+        let downloading = |downloaded_bytes| InstallEvent::Downloading {
+            index: 0,
+            downloaded_bytes,
+            total_bytes: 1024 * 1024,
+        };
+
+        // a resumed download starts at 256 KB, that part isn't counted as transferred
+        progress.apply_at(&InstallEvent::ModStarted { index: 0 }, start);
+        progress.apply_at(&downloading(256 * 1024), start);
+        progress.apply_at(&downloading(512 * 1024), start + Duration::from_secs(1));
+        progress.apply_at(&downloading(768 * 1024), start + Duration::from_secs(2));
+
+        let now = start + Duration::from_secs(2);
+        assert_eq!(progress.get_speed(now), Some(256.0 * 1024.0));
+        assert_eq!(progress.get_processed_bytes(), 768 * 1024);
+        assert_eq!(progress.get_mod_fraction(0), 0.75);
+        assert_eq!(progress.get_fraction(), 0.375);
+        // 256 KB of the first mod and the whole second mod are left
+        assert_eq!(progress.get_eta(now), Some(Duration::from_secs(5)));
+
+        // nothing arrived for longer than the window
+        assert_eq!(progress.get_speed(now + Duration::from_secs(10)), None);
+    }
+}
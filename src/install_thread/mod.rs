@@ -1,194 +1,840 @@
 use std::{
-    fs::File,
-    io,
-    path::Path,
-    sync::{Arc, Mutex}
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
 };
 
-use reqwest::Client;
 use tempdir::TempDir;
+use tokio::sync::{mpsc, Semaphore};
 
-use crate::common;
+use crate::backup::BackupSession;
+use crate::common::{self, UnpackLimits};
+use crate::error::{Error, ModError, Result};
 use crate::install_task;
+use crate::manifest::{ManifestFile, ModManifest};
+use crate::retry::RetryPolicy;
+use crate::server::Session;
 use crate::JsonModTemplate;
+use event::InstallEventSender;
+pub use event::{InstallEvent, InstallProgress};
+use transaction::InstallTransaction;
+
+mod download;
+mod event;
+mod transaction;
+
+// a file unpacked to the temporary directory with its manifest entry
+type StagedFile = (PathBuf, ManifestFile);
+
+#[derive(Clone, Debug)]
+pub struct PlannedTask {
+    pub source_path: String,
+    pub target_path: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct InstallPlan {
+    pub checksum_md5: String,
+    pub filename: String,
+    pub tasks: Vec<PlannedTask>,
+    pub files: Vec<ManifestFile>,
+}
+
+impl InstallPlan {
+    pub fn get_overwritten_files(&self) -> Vec<&ManifestFile> {
+        self.files.iter().filter(|file| file.overwritten).collect()
+    }
+
+    pub fn describe(&self) -> String {
+        let mut lines = vec![format!("{}:", self.filename)];
+        for task in self.tasks.iter() {
+            lines.push(format!("  {} -> {}", task.source_path, task.target_path));
+        }
+        let overwritten_files = self.get_overwritten_files();
+        lines.push(format!(
+            "  {} files, {} existing files overwritten",
+            self.files.len(),
+            overwritten_files.len()
+        ));
+        for file in overwritten_files.iter() {
+            lines.push(format!("    overwrites {}", file.path));
+        }
+        lines.join("\n")
+    }
+}
 
 pub trait InstallThreadTrait {
-    fn new(client: Client, task_list: Vec<JsonModTemplate>) -> Self;
+    fn new(
+        session: Session,
+        task_list: Vec<JsonModTemplate>,
+        download_concurrency: usize,
+        unpack_limits: UnpackLimits,
+        retry_policy: RetryPolicy,
+        backup_dir_path: PathBuf,
+    ) -> Self;
     fn start(&mut self, assetto_path: String) -> tokio::task::JoinHandle<()>;
-    fn get_error_list(&self) -> Vec<String>;
-    fn get_status(&self) -> String;
+    fn start_dry_run(&mut self, assetto_path: String) -> tokio::task::JoinHandle<()>;
+    fn subscribe(&self) -> mpsc::UnboundedReceiver<InstallEvent>;
+    fn cancel(&self);
+    fn discard_downloads(&self);
+    fn is_cancelled(&self) -> bool;
+    fn get_backup_session_id(&self) -> Option<String>;
+    fn get_error_list(&self) -> Vec<ModError>;
+    fn get_install_plans(&self) -> Vec<InstallPlan>;
+    fn get_installed_mod_manifests(&self) -> Vec<ModManifest>;
+    fn get_mod_outcomes(&self) -> Vec<Option<ModOutcome>>;
     fn get_successfully_installed_mods(&self) -> Vec<String>;
-    fn is_finished(&self) -> bool;
+    fn get_task_list(&self) -> Vec<JsonModTemplate>;
 }
 
 pub struct InstallThread {
-    client: Arc<Mutex<Client>>,
-    current_status: Arc<Mutex<String>>,
-    error_list: Arc<Mutex<Vec<String>>>,
-    is_finished: Arc<Mutex<bool>>,
-    successful_mods_md5: Arc<Mutex<Vec<String>>>,
+    backup_dir_path: PathBuf,
+    backup_session_id: Arc<Mutex<Option<String>>>,
+    cancelled: Arc<Mutex<bool>>,
+    download_concurrency: usize,
+    download_dir_path: PathBuf,
+    dry_run: bool,
+    error_list: Arc<Mutex<Vec<ModError>>>,
+    events: InstallEventSender,
+    install_plans: Arc<Mutex<Vec<InstallPlan>>>,
+    installed_mod_manifests: Arc<Mutex<Vec<ModManifest>>>,
+    outcomes: Arc<Mutex<Vec<Option<ModOutcome>>>>,
+    retry_policy: RetryPolicy,
+    session: Session,
     task_list: Arc<Mutex<Vec<JsonModTemplate>>>,
+    unpack_limits: UnpackLimits,
+}
+
+fn list_task_files(
+    source_path: &Path,
+    target_path: &Path,
+    assetto_path: &Path,
+) -> Result<Vec<StagedFile>> {
+    let mut files = vec![];
+    for entry in walkdir::WalkDir::new(source_path) {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative_path = target_path.join(entry.path().strip_prefix(source_path).unwrap());
+        files.push((
+            entry.path().to_path_buf(),
+            ManifestFile {
+                path: common::to_relative_path_string(&relative_path),
+                size_in_bytes: entry.metadata().map(|m| m.len()).unwrap_or(0),
+                checksum_md5: common::md5_file(entry.path()).map_err(|error| {
+                    Error::Filesystem(format!("cannot read {}: {}", entry.path().display(), error))
+                })?,
+                overwritten: assetto_path.join(&relative_path).exists(),
+            },
+        ));
+    }
+    Ok(files)
 }
 
-const MOD_DOWNLOAD_LINK: &str = "http://127.0.0.1:8080/mod_management/download?hash=";  // todo: move this to config.json or some kind of build env var
+fn stage_archive(
+    archive_path: &str,
+    assetto_path: &str,
+    temporary_directory: &Path,
+    unpack_limits: &UnpackLimits,
+) -> Result<(Vec<PlannedTask>, Vec<StagedFile>)> {
+    common::unpack_archive(
+        Path::new(archive_path),
+        temporary_directory,
+        unpack_limits,
+        &[Path::new(assetto_path)],
+    )?;
+    let entry_list = common::recursive_ls(temporary_directory);
+    let install_tasks = install_task::determine_install_tasks(&entry_list)?;
 
-fn get_download_link(md5_hash: &String) -> String {
-    format!("{}{}", MOD_DOWNLOAD_LINK, md5_hash)
+    let mut planned_tasks = vec![];
+    let mut staged_files = vec![];
+    for task in install_tasks {
+        let source_path = Path::new(&task.source_path);
+        let relative_target_path =
+            Path::new(&task.target_path).join(source_path.file_name().unwrap());
+        planned_tasks.push(PlannedTask {
+            source_path: common::to_relative_path_string(
+                source_path.strip_prefix(temporary_directory).unwrap(),
+            ),
+            target_path: common::to_relative_path_string(&relative_target_path),
+        });
+        staged_files.append(&mut list_task_files(
+            source_path,
+            &relative_target_path,
+            Path::new(assetto_path),
+        )?);
+    }
+    Ok((planned_tasks, staged_files))
 }
 
-fn install_archive(archive_path: &str, assetto_path: &str) -> compress_tools::Result<()> {
+fn plan_archive(
+    archive_path: &str,
+    assetto_path: &str,
+    task: &JsonModTemplate,
+    unpack_limits: &UnpackLimits,
+) -> Result<InstallPlan> {
     let temp_dir = TempDir::new("assetto_sync_unpack")?;
-    let temporary_directory = temp_dir.path();
-    common::unpack_archive(Path::new(archive_path), temporary_directory)?;
-    for task in
-        install_task::determine_install_tasks(&common::recursive_ls(temporary_directory)).unwrap()
-    {
-        let target_path = Path::new(assetto_path).join(task.target_path);
-        println!(
-            "{} -> {}",
-            task.source_path,
-            target_path.display().to_string()
-        );
-        let options = fs_extra::dir::CopyOptions {
-            overwrite: true,
-            skip_exist: false,
-            buffer_size: 65536,
-            copy_inside: false,
-            content_only: false,
-            depth: 0,
-        };
-        let result = fs_extra::dir::move_dir(task.source_path, target_path, &options);
-        if let Err(error) = result {
-            return Err(compress_tools::Error::from(error.to_string()));
+    let (planned_tasks, staged_files) =
+        stage_archive(archive_path, assetto_path, temp_dir.path(), unpack_limits)?;
+    Ok(InstallPlan {
+        checksum_md5: task.checksum_md5.clone(),
+        filename: task.filename.clone(),
+        tasks: planned_tasks,
+        files: staged_files.into_iter().map(|(_, file)| file).collect(),
+    })
+}
+
+fn install_archive(
+    archive_path: &str,
+    assetto_path: &str,
+    backup_session: &mut BackupSession,
+    unpack_limits: &UnpackLimits,
+    on_task: &dyn Fn(&PlannedTask),
+) -> Result<Vec<ManifestFile>> {
+    // everything is staged before the game directory is touched
+    let temp_dir = TempDir::new("assetto_sync_unpack")?;
+    let (planned_tasks, staged_files) =
+        stage_archive(archive_path, assetto_path, temp_dir.path(), unpack_limits)?;
+    planned_tasks.iter().for_each(on_task);
+
+    // replaced files are copied twice, into the transaction and from there into the
+    // backup session, the space for the new files was checked while unpacking
+    let replaced_bytes: u64 = staged_files
+        .iter()
+        .filter(|(_, file)| file.overwritten)
+        .map(|(_, file)| {
+            std::fs::metadata(Path::new(assetto_path).join(&file.path))
+                .map(|metadata| metadata.len())
+                .unwrap_or(0)
+        })
+        .sum();
+    let mut transaction = InstallTransaction::new(Path::new(assetto_path))?;
+    common::check_free_space(transaction.get_backup_dir(), replaced_bytes)?;
+    common::check_free_space(backup_session.get_path(), replaced_bytes)?;
+    for (source_path, file) in staged_files.iter() {
+        if let Err(error) = transaction.install_file(source_path, Path::new(&file.path)) {
+            let error = format!("cannot install {}: {}", file.path, error);
+            return Err(transaction.abort(error));
         }
     }
-    Ok(())
+    transaction.commit(backup_session)?;
+
+    Ok(staged_files.into_iter().map(|(_, file)| file).collect())
 }
 
-impl InstallThreadTrait for InstallThread {
-    fn new(client: Client, task_list: Vec<JsonModTemplate>) -> InstallThread {
-        InstallThread {
-            client: Arc::new(Mutex::new(client)),
-            current_status: Arc::new(Mutex::new("".to_string())),
-            error_list: Arc::new(Mutex::new(vec![])),
-            is_finished: Arc::new(Mutex::new(false)),
-            successful_mods_md5: Arc::new(Mutex::new(vec![])),
-            task_list: Arc::new(Mutex::new(task_list)),
-        }
+#[derive(Clone)]
+struct Downloader {
+    session: Session,
+    download_dir_path: PathBuf,
+    retry_policy: RetryPolicy,
+}
+
+impl Downloader {
+    // a retried download resumes from what the failed attempt already wrote,
+    // returns the number of attempts next to the result
+    async fn download_mod(
+        &self,
+        task: &JsonModTemplate,
+        index: usize,
+        events: &InstallEventSender,
+        cancelled: &Arc<Mutex<bool>>,
+    ) -> (Result<PathBuf>, u32) {
+        let archive_path = match download::get_cached_archive_path(&self.download_dir_path, task) {
+            Ok(archive_path) => archive_path,
+            Err(error) => return (Err(error), 0),
+        };
+        let archive_path = archive_path.as_path();
+        let (result, attempts) = self
+            .retry_policy
+            .run(
+                || {
+                    self.session.send(|client| async move {
+                        download::download_archive(
+                            &client,
+                            self.session
+                                .get_endpoints()
+                                .mod_download(&task.checksum_md5),
+                            archive_path,
+                            task.size_in_bytes,
+                            move |bytes| {
+                                events.send(InstallEvent::Downloading {
+                                    index,
+                                    downloaded_bytes: bytes,
+                                    total_bytes: task.size_in_bytes,
+                                });
+                            },
+                            move || *cancelled.lock().unwrap(),
+                        )
+                        .await
+                    })
+                },
+                || *cancelled.lock().unwrap(),
+                |error, attempt, backoff| {
+                    events.send(InstallEvent::Retrying {
+                        index,
+                        attempt,
+                        max_attempts: self.retry_policy.max_attempts,
+                        backoff,
+                        error: error.clone(),
+                    })
+                },
+            )
+            .await;
+        (verify_download(result, archive_path, task), attempts)
     }
+}
 
-    fn start(&mut self, assetto_path: String) -> tokio::task::JoinHandle<()> {
-        let is_finished = self.is_finished.clone();
-        let status_clone = self.current_status.clone();
-        *self.current_status.lock().unwrap() = format!("starting workers");
-        let error_list = self.error_list.clone();
-        let successful_mods = self.successful_mods_md5.clone();
+fn verify_download(
+    result: Result<download::DownloadedArchive>,
+    archive_path: &Path,
+    task: &JsonModTemplate,
+) -> Result<PathBuf> {
+    if let Err(Error::Cancelled) = result {
+        download::remove_cached_archive(archive_path);
+    }
+    let archive = result?;
+    if archive.size_in_bytes != task.size_in_bytes {
+        download::remove_cached_archive(archive_path);
+        return Err(Error::Size {
+            expected: task.size_in_bytes,
+            actual: archive.size_in_bytes,
+        });
+    }
+    if !archive
+        .checksum_md5
+        .eq_ignore_ascii_case(&task.checksum_md5)
+    {
+        download::remove_cached_archive(archive_path);
+        return Err(Error::Checksum {
+            expected: task.checksum_md5.clone(),
+            actual: archive.checksum_md5,
+        });
+    }
+    Ok(archive_path.to_path_buf())
+}
 
-        let client = self.client.clone();
-        let task_list = self.task_list.clone();
+// every mod ends in exactly one of these, only succeeded mods count as installed
+#[derive(Clone, Debug, PartialEq)]
+pub enum ModOutcome {
+    Succeeded,
+    Failed(ModError),
+    Skipped,
+}
 
-        tokio::task::spawn(async move {
-            let download_dir = TempDir::new("assetto_sync_download");
-            if let Err(error) = download_dir {
-                error_list.lock().unwrap().push(format!(
-                    "Cannot create download temporary dir, error: {}",
-                    error.to_string()
-                ));
+// Keeps the outcome of each mod and sends the matching event, a mod can only
+// end once so later outcomes for the same mod are ignored
+#[derive(Clone)]
+struct OutcomeRecorder {
+    outcomes: Arc<Mutex<Vec<Option<ModOutcome>>>>,
+    error_list: Arc<Mutex<Vec<ModError>>>,
+    events: InstallEventSender,
+    dry_run: bool,
+}
+
+impl OutcomeRecorder {
+    fn record(&self, index: usize, outcome: ModOutcome) {
+        {
+            let mut outcomes = self.outcomes.lock().unwrap();
+            if outcomes[index].is_some() {
                 return;
             }
-            let download_dir = download_dir.unwrap();
-            let download_dir_path = download_dir.path();
-            println!("Download dir path: {:?}", download_dir_path);
+            outcomes[index] = Some(outcome.clone());
+        }
+        match outcome {
+            ModOutcome::Succeeded if self.dry_run => {
+                self.events.send(InstallEvent::ModPlanned { index })
+            }
+            ModOutcome::Succeeded => self.events.send(InstallEvent::ModInstalled { index }),
+            ModOutcome::Failed(error) => {
+                self.error_list.lock().unwrap().push(error.clone());
+                self.events.send(InstallEvent::ModFailed { index, error });
+            }
+            ModOutcome::Skipped => self.events.send(InstallEvent::ModCancelled { index }),
+        }
+    }
 
-            let client = client.lock().unwrap().clone();
-            let task_list = task_list.lock().unwrap().clone();
+    // mods which never got to the install step, e.g. because their download task
+    // panicked, must not be left without an outcome
+    fn skip_unfinished(&self) {
+        let count = self.outcomes.lock().unwrap().len();
+        for index in 0..count {
+            self.record(index, ModOutcome::Skipped);
+        }
+    }
+}
+
+impl InstallThread {
+    fn spawn(&mut self, assetto_path: String, dry_run: bool) -> tokio::task::JoinHandle<()> {
+        let task_list = self.task_list.lock().unwrap().clone();
+        self.dry_run = dry_run;
+        *self.outcomes.lock().unwrap() = vec![None; task_list.len()];
+
+        let backup_session_id = self.backup_session_id.clone();
+        let backup_dir_path = self.backup_dir_path.clone();
+        let cancelled = self.cancelled.clone();
+        let events = self.events.clone();
+        let install_plans = self.install_plans.clone();
+        let installed_mod_manifests = self.installed_mod_manifests.clone();
+        let recorder = OutcomeRecorder {
+            outcomes: self.outcomes.clone(),
+            error_list: self.error_list.clone(),
+            events: self.events.clone(),
+            dry_run,
+        };
+
+        let download_concurrency = self.download_concurrency;
+        let downloader = Downloader {
+            session: self.session.clone(),
+            download_dir_path: self.download_dir_path.clone(),
+            retry_policy: self.retry_policy.clone(),
+        };
+        let unpack_limits = self.unpack_limits.clone();
+
+        tokio::task::spawn(async move {
+            eprintln!("Download dir path: {:?}", downloader.download_dir_path);
+
+            // downloads run concurrently, finished archives are queued for installation
+            // which happens one at a time to avoid conflicts in the game directory
+            let semaphore = Arc::new(Semaphore::new(download_concurrency));
+            let (sender, mut receiver) = mpsc::unbounded_channel::<(usize, PathBuf)>();
             for (index, task) in task_list.iter().enumerate() {
-                *status_clone.lock().unwrap() = format!(
-                    "Downloading mod {} ({}/{})",
-                    task.filename,
-                    index + 1,
-                    task_list.len()
-                );
-                let link = get_download_link(&task.checksum_md5);
-                let resp = client.get(link).send().await;
-                if let Err(error) = resp {
-                    error_list.lock().unwrap().push(format!(
-                        "Mod {}, download error: {}",
-                        task.filename,
-                        error.to_string()
-                    ));
-                    continue;
-                }
+                let cancelled = cancelled.clone();
+                let downloader = downloader.clone();
+                let events = events.clone();
+                let recorder = recorder.clone();
+                let semaphore = semaphore.clone();
+                let sender = sender.clone();
+                let task = task.clone();
+                tokio::task::spawn(async move {
+                    let _permit = semaphore.acquire_owned().await;
+                    if *cancelled.lock().unwrap() {
+                        recorder.record(index, ModOutcome::Skipped);
+                        return;
+                    }
+                    events.send(InstallEvent::ModStarted { index });
+                    let (result, attempts) = downloader
+                        .download_mod(&task, index, &events, &cancelled)
+                        .await;
+                    match result {
+                        Ok(archive_path) => {
+                            events.send(InstallEvent::Downloaded { index });
+                            let _ = sender.send((index, archive_path));
+                        }
+                        Err(Error::Cancelled) => recorder.record(index, ModOutcome::Skipped),
+                        Err(error) => {
+                            let error = ModError::new(&task, error).with_attempts(attempts);
+                            recorder.record(index, ModOutcome::Failed(error));
+                        }
+                    }
+                });
+            }
+            drop(sender);
+
+            let backup_session = Arc::new(Mutex::new(BackupSession::new(
+                &backup_dir_path,
+                &assetto_path,
+            )));
 
-                *status_clone.lock().unwrap() = format!(
-                    "Unpacking mod {} ({}/{})",
-                    task.filename,
-                    index + 1,
-                    task_list.len()
-                );
-                let archive_path = download_dir_path.join(&task.filename);
-
-                let resp = resp.unwrap();
-                let mut out = File::create(&archive_path).unwrap();
-                let result = io::copy(&mut resp.bytes().await.unwrap().as_ref(), &mut out);
-                if let Err(error) = result {
-                    error_list.lock().unwrap().push(format!(
-                        "Mod {}, unpack error: {}",
-                        task.filename,
-                        error.to_string()
-                    ));
+            while let Some((index, archive_path)) = receiver.recv().await {
+                let task = &task_list[index];
+                // installing a mod is the smallest step which isn't interrupted,
+                // downloaded archives of cancelled mods stay in the cache
+                if *cancelled.lock().unwrap() {
+                    recorder.record(index, ModOutcome::Skipped);
                     continue;
                 }
-                let result = result.unwrap();
-                if result != task.size_in_bytes {
-                    error_list.lock().unwrap().push(format!(
-                        "Mod {}, size mismatch (expected: {}, actual: {})",
-                        task.filename, task.size_in_bytes, result
-                    ));
+                events.send(InstallEvent::Unpacking { index });
+
+                // a dry run keeps the downloaded archive, so the install after
+                // confirmation doesn't download it again
+                if dry_run {
+                    let plan_assetto_path = assetto_path.clone();
+                    let plan_task = task.clone();
+                    let plan_unpack_limits = unpack_limits.clone();
+                    let result = tokio::task::spawn_blocking(move || {
+                        plan_archive(
+                            archive_path.to_str().unwrap(),
+                            plan_assetto_path.as_str(),
+                            &plan_task,
+                            &plan_unpack_limits,
+                        )
+                    })
+                    .await
+                    .unwrap_or_else(|error| Err(Error::Unpack(error.to_string())));
+
+                    let outcome = match result {
+                        Ok(plan) => {
+                            install_plans.lock().unwrap().push(plan);
+                            ModOutcome::Succeeded
+                        }
+                        Err(error) => ModOutcome::Failed(ModError::new(task, error)),
+                    };
+                    recorder.record(index, outcome);
                     continue;
                 }
 
-                *status_clone.lock().unwrap() = format!(
-                    "Installing mod {} ({}/{})",
-                    task.filename,
-                    index + 1,
-                    task_list.len()
-                );
-                let result = install_archive(
-                    archive_path.to_str().unwrap(),
-                    assetto_path.clone().as_str(),
-                );
-                if let Err(error) = result {
-                    error_list.lock().unwrap().push(format!(
-                        "Mod {}, install error: {}",
-                        task.filename,
-                        error.to_string()
-                    ));
+                let install_assetto_path = assetto_path.clone();
+                let install_archive_path = archive_path.clone();
+                let install_backup_session = backup_session.clone();
+                let install_unpack_limits = unpack_limits.clone();
+                let install_events = events.clone();
+                let result = tokio::task::spawn_blocking(move || {
+                    install_archive(
+                        install_archive_path.to_str().unwrap(),
+                        install_assetto_path.as_str(),
+                        &mut install_backup_session.lock().unwrap(),
+                        &install_unpack_limits,
+                        &|task| {
+                            install_events.send(InstallEvent::InstallingTask {
+                                index,
+                                source_path: task.source_path.clone(),
+                                target_path: task.target_path.clone(),
+                            })
+                        },
+                    )
+                })
+                .await
+                .unwrap_or_else(|error| Err(Error::Filesystem(error.to_string())));
+                download::remove_cached_archive(&archive_path);
+
+                let files = match result {
+                    Ok(files) => files,
+                    Err(error) => {
+                        recorder.record(index, ModOutcome::Failed(ModError::new(task, error)));
+                        continue;
+                    }
+                };
+
+                // the files are in place at this point, a backup which can't be saved
+                // is reported but doesn't make the install fail
+                {
+                    let mut backup_session = backup_session.lock().unwrap();
+                    backup_session.add_mod(&task.checksum_md5, &task.filename);
+                    match backup_session.save() {
+                        Ok(()) => {
+                            *backup_session_id.lock().unwrap() = Some(backup_session.id.clone())
+                        }
+                        Err(error) => recorder
+                            .error_list
+                            .lock()
+                            .unwrap()
+                            .push(ModError::new(task, error)),
+                    }
                 }
 
-                successful_mods
-                    .lock()
-                    .unwrap()
-                    .push(task.checksum_md5.clone());
+                installed_mod_manifests.lock().unwrap().push(ModManifest {
+                    checksum_md5: task.checksum_md5.clone(),
+                    filename: task.filename.clone(),
+                    files,
+                });
+                recorder.record(index, ModOutcome::Succeeded);
             }
-            *status_clone.lock().unwrap() = format!("Finished");
-            *is_finished.lock().unwrap() = true;
+            recorder.skip_unfinished();
+            events.send(InstallEvent::Finished);
         })
     }
+}
+
+impl InstallThreadTrait for InstallThread {
+    fn new(
+        session: Session,
+        task_list: Vec<JsonModTemplate>,
+        download_concurrency: usize,
+        unpack_limits: UnpackLimits,
+        retry_policy: RetryPolicy,
+        backup_dir_path: PathBuf,
+    ) -> InstallThread {
+        InstallThread {
+            backup_dir_path,
+            backup_session_id: Arc::new(Mutex::new(None)),
+            cancelled: Arc::new(Mutex::new(false)),
+            download_concurrency: std::cmp::max(download_concurrency, 1),
+            download_dir_path: download::get_download_cache_dir(),
+            dry_run: false,
+            error_list: Arc::new(Mutex::new(vec![])),
+            events: InstallEventSender::new(),
+            install_plans: Arc::new(Mutex::new(vec![])),
+            installed_mod_manifests: Arc::new(Mutex::new(vec![])),
+            outcomes: Arc::new(Mutex::new(vec![])),
+            retry_policy,
+            session,
+            task_list: Arc::new(Mutex::new(task_list)),
+            unpack_limits,
+        }
+    }
+
+    fn start(&mut self, assetto_path: String) -> tokio::task::JoinHandle<()> {
+        self.spawn(assetto_path, false)
+    }
+
+    fn start_dry_run(&mut self, assetto_path: String) -> tokio::task::JoinHandle<()> {
+        self.spawn(assetto_path, true)
+    }
+
+    // only events sent after subscribing are received, so subscribe before starting
+    fn subscribe(&self) -> mpsc::UnboundedReceiver<InstallEvent> {
+        self.events.subscribe()
+    }
 
-    fn get_error_list(&self) -> Vec<String> {
+    // running downloads stop at the next chunk, a mod which is being installed is
+    // finished first so the game directory is never left half-written
+    fn cancel(&self) {
+        *self.cancelled.lock().unwrap() = true;
+    }
+
+    // a dry run keeps the archives for the install, they are dropped when the plan
+    // isn't followed by one
+    fn discard_downloads(&self) {
+        for task in self.task_list.lock().unwrap().iter() {
+            if let Ok(archive_path) =
+                download::get_cached_archive_path(&self.download_dir_path, task)
+            {
+                download::remove_cached_archive(&archive_path);
+            }
+        }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        return *self.cancelled.lock().unwrap();
+    }
+
+    fn get_backup_session_id(&self) -> Option<String> {
+        return self.backup_session_id.lock().unwrap().clone();
+    }
+
+    fn get_error_list(&self) -> Vec<ModError> {
         return self.error_list.lock().unwrap().clone();
     }
 
-    fn get_status(&self) -> String {
-        return self.current_status.lock().unwrap().clone();
+    fn get_install_plans(&self) -> Vec<InstallPlan> {
+        return self.install_plans.lock().unwrap().clone();
+    }
+
+    fn get_installed_mod_manifests(&self) -> Vec<ModManifest> {
+        return self.installed_mod_manifests.lock().unwrap().clone();
+    }
+
+    // None for mods which didn't finish yet
+    fn get_mod_outcomes(&self) -> Vec<Option<ModOutcome>> {
+        return self.outcomes.lock().unwrap().clone();
     }
 
+    // a dry run only plans, nothing is installed by it
     fn get_successfully_installed_mods(&self) -> Vec<String> {
-        return self.successful_mods_md5.lock().unwrap().clone();
+        if self.dry_run {
+            return vec![];
+        }
+        let task_list = self.task_list.lock().unwrap();
+        self.outcomes
+            .lock()
+            .unwrap()
+            .iter()
+            .zip(task_list.iter())
+            .filter(|(outcome, _)| **outcome == Some(ModOutcome::Succeeded))
+            .map(|(_, task)| task.checksum_md5.clone())
+            .collect()
+    }
+
+    fn get_task_list(&self) -> Vec<JsonModTemplate> {
+        return self.task_list.lock().unwrap().clone();
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::PasswordAuthenticator;
+    use crate::endpoints::Endpoints;
+    use crate::server::{self, LoginData, StoredSession};
+    use crate::test_utils::{fixture, retry_policy, serve, task};
+    use reqwest::cookie::Jar;
+    use std::collections::HashMap;
+
+    fn install_thread(
+        server_url: &str,
+        task_list: Vec<JsonModTemplate>,
+        dir: &Path,
+    ) -> InstallThread {
+        let endpoints = Endpoints::new(server_url).unwrap();
+        let authenticator = PasswordAuthenticator::new(
+            server::build_client(Arc::new(Jar::default())).unwrap(),
+            StoredSession::default(),
+            endpoints.clone(),
+            LoginData {
+                login: "test".to_string(),
+                password: "test".to_string(),
+            },
+        );
+        let session = Session::new(Arc::new(authenticator), endpoints);
+        let mut install_thread = InstallThread::new(
+            session,
+            task_list,
+            2,
+            UnpackLimits {
+                max_size_in_bytes: 1024 * 1024,
+                max_file_count: 100,
+            },
+            retry_policy(),
+            dir.join("backups"),
+        );
+        install_thread.download_dir_path = dir.join("downloads");
+        install_thread
+    }
+
+    fn failed_kind(outcome: &Option<ModOutcome>) -> Option<&'static str> {
+        match outcome {
+            Some(ModOutcome::Failed(error)) => Some(error.error.kind()),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_mods_are_not_installed() {
+        let dir = TempDir::new("install_thread_test").unwrap();
+        let assetto_path = dir.path().join("assetto");
+        std::fs::create_dir(&assetto_path).unwrap();
+
+        let valid = fixture("valid.zip");
+        let absolute = fixture("absolute.zip");
+        let installed = task("installed.zip", &valid);
+        let missing = task("missing.zip", b"missing");
+        // the server sends other content of the same size
+        let corrupted = task("corrupted.zip", b"abcd");
+        let unavailable = task("unavailable.zip", b"unavailable");
+        let unsafe_archive = task("unsafe.zip", &absolute);
+
+        let mut responses = HashMap::new();
+        responses.insert(installed.checksum_md5.clone(), (200, valid.clone()));
+        responses.insert(corrupted.checksum_md5.clone(), (200, b"wxyz".to_vec()));
+        responses.insert(unavailable.checksum_md5.clone(), (503, vec![]));
+        responses.insert(unsafe_archive.checksum_md5.clone(), (200, absolute.clone()));
+        let server_url = serve(responses, false).await;
+
+        let task_list = vec![
+            installed.clone(),
+            missing,
+            corrupted,
+            unavailable,
+            unsafe_archive,
+        ];
+        let mut install_thread = install_thread(&server_url, task_list, dir.path());
+        install_thread
+            .start(assetto_path.to_str().unwrap().to_string())
+            .await
+            .unwrap();
+
+        let outcomes = install_thread.get_mod_outcomes();
+        assert_eq!(outcomes[0], Some(ModOutcome::Succeeded));
+        assert_eq!(failed_kind(&outcomes[1]), Some("http"));
+        assert_eq!(failed_kind(&outcomes[2]), Some("checksum"));
+        assert_eq!(failed_kind(&outcomes[3]), Some("http"));
+        assert_eq!(failed_kind(&outcomes[4]), Some("unpack"));
+        if let Some(ModOutcome::Failed(error)) = &outcomes[3] {
+            assert_eq!(error.attempts, 2);
+        }
+
+        assert_eq!(
+            install_thread.get_successfully_installed_mods(),
+            vec![installed.checksum_md5.clone()]
+        );
+        assert_eq!(install_thread.get_error_list().len(), 4);
+        assert_eq!(install_thread.get_installed_mod_manifests().len(), 1);
+        assert!(assetto_path.join("content/cars/a/data.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_installs_nothing() {
+        let dir = TempDir::new("install_thread_test").unwrap();
+        let assetto_path = dir.path().join("assetto");
+        std::fs::create_dir(&assetto_path).unwrap();
+
+        let valid = fixture("valid.zip");
+        let planned = task("planned.zip", &valid);
+        let missing = task("missing.zip", b"missing");
+        let mut responses = HashMap::new();
+        responses.insert(planned.checksum_md5.clone(), (200, valid.clone()));
+        let server_url = serve(responses, false).await;
+
+        let mut install_thread =
+            install_thread(&server_url, vec![planned.clone(), missing], dir.path());
+        install_thread
+            .start_dry_run(assetto_path.to_str().unwrap().to_string())
+            .await
+            .unwrap();
+
+        let outcomes = install_thread.get_mod_outcomes();
+        assert_eq!(outcomes[0], Some(ModOutcome::Succeeded));
+        assert_eq!(failed_kind(&outcomes[1]), Some("http"));
+        assert_eq!(install_thread.get_install_plans().len(), 1);
+        assert!(install_thread.get_successfully_installed_mods().is_empty());
+        assert!(!assetto_path.join("content").exists());
+
+        let archive_path =
+            download::get_cached_archive_path(&dir.path().join("downloads"), &planned).unwrap();
+        assert!(archive_path.exists());
+        install_thread.discard_downloads();
+        assert!(!archive_path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_mods_are_skipped() {
+        let dir = TempDir::new("install_thread_test").unwrap();
+        let valid = fixture("valid.zip");
+        let mut responses = HashMap::new();
+        let cancelled = task("cancelled.zip", &valid);
+        responses.insert(cancelled.checksum_md5.clone(), (200, valid.clone()));
+        let server_url = serve(responses, false).await;
+
+        let mut install_thread = install_thread(&server_url, vec![cancelled], dir.path());
+        install_thread.cancel();
+        install_thread
+            .start(dir.path().to_str().unwrap().to_string())
+            .await
+            .unwrap();
 
-    fn is_finished(&self) -> bool {
-        return *self.is_finished.lock().unwrap();
+        assert_eq!(
+            install_thread.get_mod_outcomes(),
+            vec![Some(ModOutcome::Skipped)]
+        );
+        assert!(install_thread.get_successfully_installed_mods().is_empty());
+        assert!(install_thread.get_error_list().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_stale_partial_download_is_dropped() {
+        let dir = TempDir::new("install_thread_test").unwrap();
+        let assetto_path = dir.path().join("assetto");
+        std::fs::create_dir(&assetto_path).unwrap();
+
+        let valid = fixture("valid.zip");
+        let installed = task("installed.zip", &valid);
+        let mut responses = HashMap::new();
+        responses.insert(installed.checksum_md5.clone(), (200, valid.clone()));
+        let server_url = serve(responses, false).await;
+
+        // left by an earlier run, it doesn't match the start of the archive
+        let archive_path =
+            download::get_cached_archive_path(&dir.path().join("downloads"), &installed).unwrap();
+        std::fs::create_dir_all(archive_path.parent().unwrap()).unwrap();
+        std::fs::write(&archive_path, vec![0u8; valid.len() / 2]).unwrap();
+
+        let mut stale = install_thread(&server_url, vec![installed.clone()], dir.path());
+        stale
+            .start(assetto_path.to_str().unwrap().to_string())
+            .await
+            .unwrap();
+        assert_eq!(failed_kind(&stale.get_mod_outcomes()[0]), Some("checksum"));
+        assert!(!archive_path.exists());
+
+        let mut retried = install_thread(&server_url, vec![installed.clone()], dir.path());
+        retried
+            .start(assetto_path.to_str().unwrap().to_string())
+            .await
+            .unwrap();
+        assert_eq!(
+            retried.get_mod_outcomes(),
+            vec![Some(ModOutcome::Succeeded)]
+        );
+        assert!(assetto_path.join("content/cars/a/data.txt").exists());
     }
 }
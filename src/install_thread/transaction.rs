@@ -0,0 +1,195 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use tempdir::TempDir;
+
+use crate::backup::BackupSession;
+use crate::common;
+use crate::error::{Error, Result};
+
+struct WrittenFile {
+    relative_path: String,
+    target_path: PathBuf,
+    backup_path: Option<PathBuf>,
+}
+
+// Files are copied into the game directory one by one, every file which gets
+// overwritten is backed up first, so a failed install can be undone completely
+pub struct InstallTransaction {
+    assetto_path: PathBuf,
+    backup_dir: TempDir,
+    created_dirs: Vec<PathBuf>,
+    written_files: Vec<WrittenFile>,
+}
+
+impl InstallTransaction {
+    pub fn new(assetto_path: &Path) -> io::Result<InstallTransaction> {
+        Ok(InstallTransaction {
+            assetto_path: assetto_path.to_path_buf(),
+            backup_dir: TempDir::new("assetto_sync_backup")?,
+            created_dirs: vec![],
+            written_files: vec![],
+        })
+    }
+
+    pub fn get_backup_dir(&self) -> &Path {
+        self.backup_dir.path()
+    }
+
+    pub fn install_file(&mut self, source_path: &Path, relative_path: &Path) -> io::Result<()> {
+        let target_path = self.assetto_path.join(relative_path);
+        if let Some(parent) = target_path.parent() {
+            self.create_dir_all(parent)?;
+        }
+
+        let mut backup_path = None;
+        if target_path.exists() {
+            let path = self.backup_dir.path().join(relative_path);
+            std::fs::create_dir_all(path.parent().unwrap())?;
+            std::fs::copy(&target_path, &path)?;
+            backup_path = Some(path);
+        }
+
+        // recorded before copying, a partially written file has to be rolled back too
+        self.written_files.push(WrittenFile {
+            relative_path: common::to_relative_path_string(relative_path),
+            target_path: target_path.clone(),
+            backup_path,
+        });
+        std::fs::copy(source_path, &target_path)?;
+        Ok(())
+    }
+
+    // backups are kept in the session, so the install can be undone later on
+    pub fn commit(self, session: &mut BackupSession) -> Result<()> {
+        let result = self.written_files.iter().try_for_each(|file| {
+            session.add_file(&file.relative_path, file.backup_path.as_deref())
+        });
+        if let Err(error) = result {
+            return Err(self.abort(error.to_string()));
+        }
+        let _ = self.backup_dir.close();
+        Ok(())
+    }
+
+    pub fn abort(self, error: String) -> Error {
+        match self.rollback() {
+            Ok(()) => Error::Filesystem(format!("{}, changes rolled back", error)),
+            Err(rollback_error) => {
+                Error::Filesystem(format!("{}, rollback failed: {}", error, rollback_error))
+            }
+        }
+    }
+
+    pub fn rollback(self) -> std::result::Result<(), String> {
+        let mut errors = vec![];
+        for file in self.written_files.iter().rev() {
+            let result = match &file.backup_path {
+                Some(backup_path) => std::fs::copy(backup_path, &file.target_path).map(|_| ()),
+                None => std::fs::remove_file(&file.target_path),
+            };
+            match result {
+                Err(error) if error.kind() != io::ErrorKind::NotFound => {
+                    errors.push(format!("{}: {}", file.target_path.display(), error));
+                }
+                _ => {}
+            }
+        }
+        for dir in self.created_dirs.iter().rev() {
+            if let Err(error) = std::fs::remove_dir(dir) {
+                errors.push(format!("{}: {}", dir.display(), error));
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(errors.join(", "));
+        }
+        Ok(())
+    }
+
+    fn create_dir_all(&mut self, path: &Path) -> io::Result<()> {
+        let mut missing_dirs = vec![];
+        for ancestor in path.ancestors() {
+            if ancestor.exists() {
+                break;
+            }
+            missing_dirs.push(ancestor.to_path_buf());
+        }
+        for dir in missing_dirs.into_iter().rev() {
+            std::fs::create_dir(&dir)?;
+            self.created_dirs.push(dir);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_file(path: &Path, content: &str) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn test_commit_keeps_installed_files() {
+        let assetto_dir = TempDir::new("assetto").unwrap();
+        let source_dir = TempDir::new("source").unwrap();
+        let source_path = source_dir.path().join("data.acd");
+        write_file(&source_path, "new");
+        write_file(&assetto_dir.path().join("content/cars/a/data.acd"), "old");
+
+        let mut transaction = InstallTransaction::new(assetto_dir.path()).unwrap();
+        transaction
+            .install_file(&source_path, Path::new("content/cars/a/data.acd"))
+            .unwrap();
+        transaction
+            .install_file(&source_path, Path::new("content/cars/b/data.acd"))
+            .unwrap();
+        let backup_dir = TempDir::new("backup").unwrap();
+        let mut session = BackupSession::new(backup_dir.path(), "");
+        transaction.commit(&mut session).unwrap();
+
+        let read = |path: &str| std::fs::read_to_string(assetto_dir.path().join(path)).unwrap();
+        assert_eq!(read("content/cars/a/data.acd"), "new");
+        assert_eq!(read("content/cars/b/data.acd"), "new");
+        assert_eq!(session.files.len(), 2);
+        assert!(session.files[0].backed_up);
+        assert!(!session.files[1].backed_up);
+    }
+
+    #[test]
+    fn test_rollback_restores_previous_state() {
+        let assetto_dir = TempDir::new("assetto").unwrap();
+        let source_dir = TempDir::new("source").unwrap();
+        let source_path = source_dir.path().join("data.acd");
+        write_file(&source_path, "new");
+        write_file(&assetto_dir.path().join("content/cars/a/data.acd"), "old");
+
+        let mut transaction = InstallTransaction::new(assetto_dir.path()).unwrap();
+        transaction
+            .install_file(&source_path, Path::new("content/cars/a/data.acd"))
+            .unwrap();
+        transaction
+            .install_file(&source_path, Path::new("content/cars/b/skins/data.acd"))
+            .unwrap();
+        assert!(transaction
+            .install_file(
+                &source_dir.path().join("missing"),
+                Path::new("content/cars/c/data.acd")
+            )
+            .is_err());
+        transaction.rollback().unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(assetto_dir.path().join("content/cars/a/data.acd")).unwrap(),
+            "old"
+        );
+        assert!(!assetto_dir.path().join("content/cars/b").exists());
+        assert!(!assetto_dir.path().join("content/cars/c").exists());
+        assert!(assetto_dir.path().join("content/cars").exists());
+    }
+}
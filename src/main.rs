@@ -1,15 +1,31 @@
+use endpoints::Endpoints;
 use install_thread::InstallThreadTrait;
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
+use server::{LoginData, Session};
 use std::{
     path::Path,
     sync::{Arc, Mutex},
+    time::Instant,
 };
+use structopt::StructOpt;
 use tokio::task::JoinHandle;
 
+mod args;
+mod auth;
+mod backup;
+mod cli;
 mod common;
 mod config;
+mod endpoints;
+mod error;
 mod install_task;
+mod manifest;
+mod retry;
+mod secret_store;
+mod server;
+#[cfg(test)]
+mod test_utils;
+mod uninstall;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct JsonModTemplate {
@@ -20,14 +36,11 @@ pub struct JsonModTemplate {
 
 use config::{ConfigObject, ConfigTrait};
 use gtk::{prelude::*, DialogExt, WidgetExt, *};
+use manifest::{ManifestObject, ManifestTrait};
 use scopeguard::guard;
 
-fn is_valid_assetto_path(path: &Path) -> bool {
-    return path.join("acs.exe").exists();
-}
-
 fn get_assetto_path(existing_path: &String) -> Result<String, String> {
-    if is_valid_assetto_path(Path::new(existing_path)) {
+    if common::is_valid_assetto_path(Path::new(existing_path)) {
         return Ok(existing_path.clone());
     }
 
@@ -46,7 +59,7 @@ fn get_assetto_path(existing_path: &String) -> Result<String, String> {
     if guard.run() == ResponseType::Accept {
         let result = guard.get_filename().unwrap();
 
-        return match is_valid_assetto_path(result.as_path()) {
+        return match common::is_valid_assetto_path(result.as_path()) {
             true => Ok(result.to_string_lossy().to_string()),
             false => Err(format!("Path {:?} does not contain acs.exe", result)),
         };
@@ -54,14 +67,6 @@ fn get_assetto_path(existing_path: &String) -> Result<String, String> {
     Err("No path provided".to_string())
 }
 
-const LOGIN_LINK: &str = "http://127.0.0.1:8080/login"; // todo: move this to config.json or some kind of build env var
-const MODS_JSON_LINK: &str = "http://127.0.0.1:8080/mods.json";
-
-struct LoginData {
-    login: String,
-    password: String,
-}
-
 fn login_dialog() -> Result<LoginData, String> {
     let glade_src = include_str!("login.glade");
     let builder = gtk::Builder::new();
@@ -85,10 +90,14 @@ fn login_dialog() -> Result<LoginData, String> {
     })
 }
 
-async fn login(login: &String, password: &String) -> Result<(LoginData, Client), String> {
+async fn login(
+    endpoints: &Endpoints,
+    login: &str,
+    password: &str,
+) -> Result<(LoginData, Session), String> {
     let mut login_data = LoginData {
-        login: login.clone(),
-        password: password.clone(),
+        login: login.to_string(),
+        password: password.to_string(),
     };
     if login_data.login.is_empty() {
         let dialog_data = login_dialog();
@@ -98,41 +107,59 @@ async fn login(login: &String, password: &String) -> Result<(LoginData, Client),
         login_data = dialog_data.unwrap();
     }
 
-    let client = reqwest::Client::builder()
-        .cookie_store(true)
-        .redirect(reqwest::redirect::Policy::custom(|attempt| attempt.stop()))
-        .build();
-    if let Err(error) = client {
-        return Err(error.to_string());
+    let session = server::login(endpoints, &login_data).await;
+    match session {
+        Ok(session) => Ok((login_data, session)),
+        Err(error) => Err(error.to_string()),
     }
+}
 
-    let client = client.unwrap();
-
-    let response = client
-        .post(LOGIN_LINK)
-        .form(&[
-            ("login", &login_data.login),
-            ("password", &login_data.password),
-        ])
-        .send()
-        .await;
-
-    if let Err(error) = response {
-        return Err(error.to_string());
+// the session of the last run, when the server still accepts it
+async fn restore_session(endpoints: &Endpoints, config: &ConfigObject) -> Option<Session> {
+    if config.get_profile().login.is_empty() {
+        return None;
+    }
+    let stored_session = config.get_stored_session()?;
+    let login_data = LoginData {
+        login: config.get_profile().login.clone(),
+        password: config.get_password(),
+    };
+    match server::restore_session(endpoints, &login_data, &stored_session).await {
+        Ok(session) => Some(session),
+        Err(_) => {
+            println!("Stored session not accepted, logging in again");
+            None
+        }
     }
+}
 
-    let response = response.unwrap();
-    let has_user_name_cookie = response.cookies().any(|c| c.name() == "user_name");
+// asks for the login until it works, fails only when the dialog is cancelled
+async fn password_session(
+    endpoints: &Endpoints,
+    config: &mut ConfigObject,
+) -> Result<Session, String> {
+    if let Some(session) = restore_session(endpoints, config).await {
+        return Ok(session);
+    }
 
-    match has_user_name_cookie {
-        true => Ok((login_data, client)),
-        false => Err("Login failed (wrong password?)".to_string()),
+    let mut login_data = login(
+        endpoints,
+        &config.get_profile().login,
+        &config.get_password(),
+    )
+    .await;
+    while let Err(error) = login_data {
+        if error == "User canceled dialog" {
+            return Err(error);
+        }
+        login_data = login(endpoints, "", "").await;
     }
-}
+    let (login_data, session) = login_data.unwrap();
 
-async fn get_mod_list(client: &Client) -> Result<Vec<JsonModTemplate>, reqwest::Error> {
-    let mod_list: Vec<JsonModTemplate> = client.get(MODS_JSON_LINK).send().await?.json().await?;
-    Ok(mod_list)
+    // the stored credentials are used again when the session expires while installing
+    config.set_login(login_data.login);
+    config.set_password(login_data.password);
+    Ok(session)
 }
 
 fn fill_mod_list(
@@ -176,6 +203,31 @@ fn get_task_list(
     task_list
 }
 
+fn get_uninstall_list(
+    lv_mods_store: Arc<Mutex<gtk::ListStore>>,
+    config: &ConfigObject,
+    mod_list: &[JsonModTemplate],
+) -> Vec<JsonModTemplate> {
+    let mut uninstall_list = vec![];
+    let store = lv_mods_store.lock().unwrap();
+    for (index, entry) in mod_list.iter().enumerate() {
+        let iter = store
+            .get_iter_from_string(index.to_string().as_str())
+            .unwrap();
+        let should_install = store.get_value(&iter, 0).get::<bool>().unwrap().unwrap();
+
+        if should_install {
+            continue;
+        }
+        if !config.is_mod_installed(&entry.checksum_md5) {
+            continue;
+        }
+
+        uninstall_list.push(entry.clone());
+    }
+    uninstall_list
+}
+
 fn display_summary(summary: &String) {
     let glade_src = include_str!("summary.glade");
     let builder = gtk::Builder::new();
@@ -194,30 +246,41 @@ fn display_summary(summary: &String) {
 
 mod install_thread;
 
-async fn install_mods(
-    client: Client,
-    lv_mods_store: Arc<Mutex<gtk::ListStore>>,
-    config: &mut ConfigObject,
-    mod_list: &Vec<JsonModTemplate>,
+async fn run_install_thread(
+    install_thread: Arc<Mutex<install_thread::InstallThread>>,
+    assetto_path: String,
+    dry_run: bool,
 ) {
     let glade_src = include_str!("worker.glade");
     let builder = gtk::Builder::new();
     let result = builder.add_from_string(glade_src);
     if let Err(error) = result {
-        panic!("failed to parse main.glade: {}", error);
+        panic!("failed to parse worker.glade: {}", error);
     }
 
-    let task_list = get_task_list(lv_mods_store, config, mod_list);
-    let install_thread = Arc::new(Mutex::new(install_thread::InstallThread::new(
-        client, task_list,
-    )));
-    let assetto_path = config.config.assetto_path.clone();
+    let task_list = install_thread.lock().unwrap().get_task_list();
+    let progress = Arc::new(Mutex::new(install_thread::InstallProgress::new(&task_list)));
+
+    // events are forwarded to the GTK main loop, widgets can't be touched from tokio
+    let (sender, receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
     let install_thread_clone = install_thread.clone();
     let task = tokio::spawn(async move {
+        let mut events;
         let result: Option<JoinHandle<()>>;
         {
             let mut install_thread = install_thread_clone.lock().unwrap();
-            result = Some(install_thread.start(assetto_path));
+            events = install_thread.subscribe();
+            result = match dry_run {
+                true => Some(install_thread.start_dry_run(assetto_path)),
+                false => Some(install_thread.start(assetto_path)),
+            };
+        }
+        // the thread keeps its sender, so the stream ends with the Finished event
+        while let Some(event) = events.recv().await {
+            let is_finished = matches!(event, install_thread::InstallEvent::Finished);
+            if sender.send(event).is_err() || is_finished {
+                break;
+            }
         }
         let _ = result.unwrap().await;
     });
@@ -225,13 +288,68 @@ async fn install_mods(
     let window: gtk::Window = builder.get_object("window1").unwrap();
 
     let label_status: gtk::Label = builder.get_object("label_status").unwrap();
+    let progress_overall: gtk::ProgressBar = builder.get_object("progress_overall").unwrap();
+    let lv_progress_store: gtk::ListStore = builder.get_object("lv_progress_store").unwrap();
+    let tv_log: gtk::TextView = builder.get_object("tv_log").unwrap();
+    let button_cancel: gtk::Button = builder.get_object("button_cancel").unwrap();
 
-    let install_thread_clone = install_thread.clone();
-    glib::timeout_add_local(100, move || {
-        let install_thread = install_thread_clone.lock().unwrap();
-        label_status.set_text(install_thread.get_status().as_str());
+    // closing the window cancels as well, it stays open until the thread stops
+    let cancel_install_thread = install_thread.clone();
+    let cancel_button = button_cancel.clone();
+    let cancel = move || {
+        cancel_install_thread.lock().unwrap().cancel();
+        cancel_button.set_sensitive(false);
+    };
+    let cancel_clone = cancel.clone();
+    button_cancel.connect_clicked(move |_| cancel_clone());
+    window.connect_delete_event(move |_, _| {
+        cancel();
+        Inhibit(true)
+    });
+
+    for task in task_list.iter() {
+        lv_progress_store.insert_with_values(
+            None,
+            &[0, 1, 2],
+            &[&task.filename, &0, &"Queued".to_string()],
+        );
+    }
+
+    let log_buffer = tv_log.get_buffer().unwrap();
+    let log_end = log_buffer
+        .create_mark(None, &log_buffer.get_end_iter(), false)
+        .unwrap();
+    let event_progress = progress.clone();
+    receiver.attach(None, move |event| {
+        let mut progress = event_progress.lock().unwrap();
+        progress.apply(&event);
+        if let Some(line) = progress.describe_event(&event) {
+            log_buffer.insert(
+                &mut log_buffer.get_end_iter(),
+                format!("{}\n", line).as_str(),
+            );
+            tv_log.scroll_to_mark(&log_end, 0.0, false, 0.0, 1.0);
+        }
+        Continue(!progress.is_finished())
+    });
 
-        if install_thread.is_finished() {
+    // downloads report every chunk, so the widgets are refreshed on a timer instead
+    glib::timeout_add_local(200, move || {
+        let progress = progress.lock().unwrap();
+        progress_overall.set_fraction(progress.get_fraction());
+        progress_overall.set_text(Some(
+            format!("{:.0}%", progress.get_fraction() * 100.0).as_str(),
+        ));
+        label_status.set_text(progress.describe_transfer(Instant::now()).as_str());
+        for (index, (_, status)) in progress.get_mod_status().iter().enumerate() {
+            let row = lv_progress_store.get_iter_from_string(&index.to_string());
+            if let Some(row) = row {
+                let percent = (progress.get_mod_fraction(index) * 100.0) as i32;
+                lv_progress_store.set(&row, &[1, 2], &[&percent, &status.describe()]);
+            }
+        }
+
+        if progress.is_finished() {
             gtk::main_quit();
             Continue(false)
         } else {
@@ -244,45 +362,338 @@ async fn install_mods(
     gtk::main();
     window.hide();
 
+    let _ = task.await;
+}
+
+fn confirm_install_plan(
+    uninstall_list: &[JsonModTemplate],
+    plans: &[install_thread::InstallPlan],
+    error_list: &[error::ModError],
+) -> bool {
+    let mut lines = vec![];
+    for entry in uninstall_list.iter() {
+        lines.push(format!("{}: uninstall", entry.filename));
+    }
+    for plan in plans.iter() {
+        lines.push(plan.describe());
+    }
+    if !error_list.is_empty() {
+        lines.push("Errors, these mods will not be installed:".to_string());
+        for error in error_list.iter() {
+            lines.push(error.to_string());
+        }
+    }
+    if lines.is_empty() {
+        lines.push("No changes.".to_string());
+    }
+
+    let glade_src = include_str!("plan.glade");
+    let builder = gtk::Builder::new();
+    let result = builder.add_from_string(glade_src);
+    if let Err(error) = result {
+        panic!("failed to parse plan.glade: {}", error);
+    }
+
+    let dialog: gtk::Dialog = builder.get_object("dialog").unwrap();
+    let tv_plan: gtk::TextView = builder.get_object("tv_plan").unwrap();
+    tv_plan
+        .get_buffer()
+        .unwrap()
+        .set_text(lines.join("\n").as_str());
+
+    let response = dialog.run();
+    dialog.hide();
+    response == ResponseType::Ok
+}
+
+async fn install_mods(
+    session: Session,
+    lv_mods_store: Arc<Mutex<gtk::ListStore>>,
+    config: &mut ConfigObject,
+    manifest: &mut ManifestObject,
+    mod_list: &Vec<JsonModTemplate>,
+    preview: bool,
+) {
+    let assetto_path = config.get_profile().assetto_path.clone();
+    let uninstall_list = get_uninstall_list(lv_mods_store.clone(), config, mod_list);
+    let mut task_list = get_task_list(lv_mods_store, config, mod_list);
+
+    // the preview downloads and unpacks the mods without touching the game directory
+    if preview {
+        let plan_thread = Arc::new(Mutex::new(install_thread::InstallThread::new(
+            session.clone(),
+            task_list.clone(),
+            config.config.download_concurrency,
+            config.get_unpack_limits(),
+            config.get_retry_policy(),
+            config.get_backup_dir(),
+        )));
+        run_install_thread(plan_thread.clone(), assetto_path.clone(), true).await;
+
+        let plan_thread = plan_thread.lock().unwrap();
+        if plan_thread.is_cancelled() {
+            println!("Install plan cancelled");
+            return;
+        }
+        let plans = plan_thread.get_install_plans();
+        if !confirm_install_plan(&uninstall_list, &plans, &plan_thread.get_error_list()) {
+            println!("Install plan rejected");
+            plan_thread.discard_downloads();
+            return;
+        }
+        task_list.retain(|entry| {
+            plans
+                .iter()
+                .any(|plan| plan.checksum_md5 == entry.checksum_md5)
+        });
+    }
+
+    let mut uninstalled_mods_count = 0;
+    let mut uninstall_error_list = vec![];
+    for entry in uninstall_list.iter() {
+        let result = uninstall::uninstall_mod(
+            Path::new(&assetto_path),
+            &entry.checksum_md5,
+            config,
+            manifest,
+        );
+        match result {
+            Ok(_) => uninstalled_mods_count += 1,
+            Err(error) => uninstall_error_list.push(error.to_string()),
+        }
+    }
+
+    let install_thread = Arc::new(Mutex::new(install_thread::InstallThread::new(
+        session,
+        task_list,
+        config.config.download_concurrency,
+        config.get_unpack_limits(),
+        config.get_retry_policy(),
+        config.get_backup_dir(),
+    )));
+    run_install_thread(install_thread.clone(), assetto_path, false).await;
+
     let install_thread = install_thread.lock().unwrap();
 
     let successfully_installed_mods = install_thread.get_successfully_installed_mods();
     for checksum in successfully_installed_mods.iter() {
         config.add_installed_mod(checksum);
     }
-
-    let error_list = install_thread.get_error_list();
-    if error_list.len() == 0 {
-        display_summary(&format!(
-            "{} mods installed successfully.",
-            successfully_installed_mods.len()
-        ));
-    } else {
-        let mut summary = format!(
-            "{} mods installed successfully.\nErrors:\n",
-            successfully_installed_mods.len()
-        );
+    let mut error_list = uninstall_error_list;
+    for error in install_thread.get_error_list() {
+        error_list.push(error.to_string());
+    }
+    for mod_manifest in install_thread.get_installed_mod_manifests() {
+        let filename = mod_manifest.filename.clone();
+        if let Err(error) = manifest.add_mod(mod_manifest) {
+            error_list.push(format!("Mod {}, {}", filename, error));
+        }
+    }
+    let mut summary = format!(
+        "{} mods installed successfully.",
+        successfully_installed_mods.len()
+    );
+    if uninstalled_mods_count > 0 {
+        summary += format!("\n{} mods uninstalled.", uninstalled_mods_count).as_str();
+    }
+    if let Some(id) = install_thread.get_backup_session_id() {
+        summary += format!("\nReplaced files were backed up as {}.", id).as_str();
+    }
+    if install_thread.is_cancelled() {
+        summary += "\nCancelled, the remaining mods were not installed.";
+    }
+    if !error_list.is_empty() {
+        summary += "\nErrors:\n";
         for error in error_list.iter() {
             summary += format!("{}\n", error).as_str();
         }
-        display_summary(&summary);
     }
+    display_summary(&summary);
+}
 
-    let _ = task.await;
+fn restore_backup(config: &mut ConfigObject, manifest: &mut ManifestObject) {
+    let backup_dir = config.get_backup_dir();
+    let sessions = backup::list_sessions(&backup_dir);
+    if sessions.is_empty() {
+        display_summary(&"There are no backups to restore.".to_string());
+        return;
+    }
+
+    let glade_src = include_str!("restore.glade");
+    let builder = gtk::Builder::new();
+    let result = builder.add_from_string(glade_src);
+    if let Err(error) = result {
+        panic!("failed to parse restore.glade: {}", error);
+    }
+
+    let dialog: gtk::Dialog = builder.get_object("dialog").unwrap();
+    let cb_backups: gtk::ComboBoxText = builder.get_object("cb_backups").unwrap();
+    for session in sessions.iter() {
+        cb_backups.append_text(session.describe().as_str());
+    }
+    cb_backups.set_active(Some(0));
+
+    let response = dialog.run();
+    dialog.hide();
+    if response != ResponseType::Ok {
+        return;
+    }
+    let index = cb_backups.get_active();
+    if index.is_none() {
+        return;
+    }
+    let session = &sessions[index.unwrap() as usize];
+
+    let assetto_path = config.get_profile().assetto_path.clone();
+    let result = backup::restore_session(
+        Path::new(&assetto_path),
+        &backup_dir,
+        &session.id,
+        config,
+        manifest,
+    );
+    match result {
+        Ok(result) => display_summary(&format!(
+            "Backup {} restored.\n{} files restored, {} files removed.",
+            session.id,
+            result.restored_files.len(),
+            result.removed_files.len()
+        )),
+        Err(error) => display_error(&error.to_string()),
+    }
+}
+
+// None when the dialog was cancelled
+fn profile_dialog(config: &ConfigObject) -> Option<String> {
+    let glade_src = include_str!("profile.glade");
+    let builder = gtk::Builder::new();
+    let result = builder.add_from_string(glade_src);
+    if let Err(error) = result {
+        panic!("failed to parse profile.glade: {}", error);
+    }
+
+    let dialog: gtk::Dialog = builder.get_object("dialog").unwrap();
+    let cb_profiles: gtk::ComboBoxText = builder.get_object("cb_profiles").unwrap();
+    let profile_names = config.get_profile_names();
+    for name in profile_names.iter() {
+        cb_profiles.append_text(name.as_str());
+    }
+    let selected = profile_names
+        .iter()
+        .position(|name| name == config.get_profile_name());
+    cb_profiles.set_active(selected.map(|index| index as u32));
+
+    let response = dialog.run();
+    dialog.hide();
+    if response != ResponseType::Ok {
+        return None;
+    }
+    let profile = cb_profiles.get_active_text()?.trim().to_string();
+    match profile.is_empty() {
+        true => None,
+        false => Some(profile),
+    }
+}
+
+// --server-url is stored in a profile created with it
+fn select_profile(
+    config: &mut ConfigObject,
+    profile: &str,
+    server_url: &Option<String>,
+) -> Result<(), error::Error> {
+    let created = config.select_profile(profile)?;
+    if let (true, Some(server_url)) = (created, server_url) {
+        config.set_server_url(server_url.clone());
+    }
+    Ok(())
+}
+
+fn display_error(error: &str) {
+    let dialog = MessageDialog::new(
+        None::<&Window>,
+        DialogFlags::MODAL,
+        MessageType::Error,
+        ButtonsType::Ok,
+        error,
+    );
+    dialog.run();
+    dialog.hide();
 }
 
 #[tokio::main]
 async fn main() -> reqwest::Result<()> {
-    if gtk::init().is_err() {
-        println!("Failed to initialize GTK.");
-        return Ok(());
-    }
+    let args = args::Args::from_args();
 
     let config_dir = dirs::config_dir().unwrap();
     let config_file = config_dir.join("assetto_sync_client.json");
     let mut config = config::ConfigObject::new(config_file.to_str().unwrap());
 
-    let mut assetto_path = get_assetto_path(&config.config.assetto_path);
+    let gui = args.command.is_none();
+    if gui && gtk::init().is_err() {
+        println!("Failed to initialize GTK.");
+        return Ok(());
+    }
+    // stderr keeps the --json output intact
+    for warning in config.get_warnings().iter() {
+        eprintln!("Warning: {}", warning);
+        if gui {
+            display_error(warning);
+        }
+    }
+
+    // the picker is only shown when there is a choice
+    let profile = match &args.profile {
+        Some(profile) => Some(profile.clone()),
+        None if gui && config.get_profile_names().len() > 1 => match profile_dialog(&config) {
+            Some(profile) => Some(profile),
+            None => {
+                println!("Closing: no profile selected");
+                return Ok(());
+            }
+        },
+        None => None,
+    };
+    if let Some(profile) = profile {
+        if let Err(error) = select_profile(&mut config, &profile, &args.server_url) {
+            if !gui {
+                std::process::exit(cli::report_error(
+                    args.json,
+                    cli::EXIT_CONFIG,
+                    &error.to_string(),
+                ));
+            }
+            println!("Error: {}", error);
+            display_error(&error.to_string());
+            return Ok(());
+        }
+    }
+    let mut manifest = manifest::ManifestObject::new(&config.get_manifest_path());
+
+    let server_url = args
+        .server_url
+        .clone()
+        .unwrap_or(config.get_profile().server_url.clone());
+    let endpoints = Endpoints::new(&server_url);
+
+    if let Some(command) = &args.command {
+        let exit_code = match endpoints {
+            Ok(endpoints) => cli::run(command, &args, &mut config, &mut manifest, &endpoints).await,
+            Err(error) => cli::report_error(args.json, cli::EXIT_CONFIG, &error.to_string()),
+        };
+        std::process::exit(exit_code);
+    }
+
+    if let Err(error) = endpoints {
+        println!("Error: {}", error);
+        display_error(&error.to_string());
+        return Ok(());
+    }
+    let endpoints = endpoints.unwrap();
+
+    if let Some(path) = &args.assetto_path {
+        config.get_profile_mut().assetto_path = path.clone();
+    }
+    let mut assetto_path = get_assetto_path(&config.get_profile().assetto_path);
     while let Err(error) = &assetto_path {
         let dialog = MessageDialog::new(
             None::<&Window>,
@@ -299,30 +710,33 @@ async fn main() -> reqwest::Result<()> {
             println!("Error: {}", error.to_string());
             return Ok(());
         }
-        assetto_path = get_assetto_path(&config.config.assetto_path);
+        assetto_path = get_assetto_path(&config.get_profile().assetto_path);
     }
 
     config.set_assetto_path(assetto_path.unwrap());
 
-    let mut login_data = login(&config.config.login, &config.config.password).await;
-    while let Err(error) = login_data {
-        if error == "User canceled dialog" {
-            println!("Closing: {}", error);
-            return Ok(());
-        }
-        login_data = login(&"".to_string(), &"".to_string()).await;
+    let api_token = auth::get_api_token(&args.api_token, &config.get_api_token());
+    let session = match api_token {
+        Some(api_token) => server::token_session(&endpoints, &api_token).map_err(|error| {
+            display_error(&error.to_string());
+            error.to_string()
+        }),
+        None => password_session(&endpoints, &mut config).await,
+    };
+    if let Err(error) = session {
+        println!("Closing: {}", error);
+        return Ok(());
     }
+    let session = session.unwrap();
+    cli::save_session(&mut config, &session);
 
-    let login_data = login_data.unwrap();
-    let client = login_data.1;
-    let login_data = login_data.0;
-
-    config.set_login(login_data.login);
-    config.set_password(login_data.password);
-
-    let mod_list = get_mod_list(&client).await;
+    let (mod_list, attempts) = server::get_mod_list(&session, &config.get_retry_policy()).await;
     if let Err(error) = mod_list {
-        println!("Error receiving mods: {}", error.to_string());
+        println!(
+            "Error receiving mods: {}{}",
+            error,
+            retry::describe_attempts(attempts)
+        );
         return Ok(());
     }
     let mod_list = mod_list.unwrap();
@@ -374,6 +788,18 @@ async fn main() -> reqwest::Result<()> {
         gtk::main_quit();
     });
 
+    let restore_selected = Arc::new(Mutex::new(false));
+
+    let window_button_restore = window.clone();
+    let button_restore: gtk::Button = builder.get_object("button_restore").unwrap();
+    let restore_selected_clone = restore_selected.clone();
+    button_restore.connect_clicked(move |_| {
+        let window = window_button_restore.lock().unwrap();
+        window.set_visible(false);
+        *restore_selected_clone.lock().unwrap() = true;
+        gtk::main_quit();
+    });
+
     let button_cancel: gtk::Button = builder.get_object("button_cancel").unwrap();
     let window_button_cancel = window.clone();
     button_cancel.connect_clicked(move |_| {
@@ -385,11 +811,26 @@ async fn main() -> reqwest::Result<()> {
     window.lock().unwrap().show_all();
     gtk::main();
 
+    if *restore_selected.lock().unwrap() {
+        restore_backup(&mut config, &mut manifest);
+        return Ok(());
+    }
+
     if *install_selected.lock().unwrap() == false {
         println!("Cancel clicked");
         return Ok(());
     }
 
-    install_mods(client, lv_mods_store.clone(), &mut config, &mod_list).await;
+    let cb_preview: gtk::CheckButton = builder.get_object("cb_preview").unwrap();
+    install_mods(
+        session.clone(),
+        lv_mods_store.clone(),
+        &mut config,
+        &mut manifest,
+        &mod_list,
+        cb_preview.get_active(),
+    )
+    .await;
+    cli::save_session(&mut config, &session);
     Ok(())
 }
@@ -0,0 +1,186 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::common;
+use crate::error::{Error, Result};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ManifestFile {
+    pub path: String,
+    pub size_in_bytes: u64,
+    pub checksum_md5: String,
+    pub overwritten: bool,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ModManifest {
+    pub checksum_md5: String,
+    pub filename: String,
+    pub files: Vec<ManifestFile>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub mods: Vec<ModManifest>,
+}
+
+pub trait ManifestTrait {
+    fn new(path: &str) -> Self;
+    fn add_mod(&mut self, mod_manifest: ModManifest) -> Result<()>;
+    fn get_mod(&self, md5: &str) -> Option<&ModManifest>;
+    fn remove_mod(&mut self, md5: &str) -> Result<()>;
+}
+
+pub struct ManifestObject {
+    pub manifest: Manifest,
+    pub path: String,
+}
+
+fn read_manifest(path: &str) -> Manifest {
+    let file = std::fs::read_to_string(path);
+    match file {
+        Ok(file) => match serde_json::from_str(&file) {
+            Ok(manifest) => manifest,
+            Err(error) => {
+                eprintln!("Cannot parse manifest {}: {}", path, error);
+                Manifest::default()
+            }
+        },
+        Err(_) => Manifest::default(),
+    }
+}
+
+// written like the config, a crash while writing leaves the previous manifest in place
+fn write_manifest_to_json(path: &Path, manifest: &Manifest) -> Result<()> {
+    let json = serde_json::to_string_pretty(manifest).unwrap();
+    if let Err(error) = common::write_atomically(path, json.as_bytes()) {
+        return Err(Error::Filesystem(format!(
+            "cannot write the manifest {}: {}",
+            path.display(),
+            error
+        )));
+    }
+    Ok(())
+}
+
+impl ManifestTrait for ManifestObject {
+    fn new(path: &str) -> ManifestObject {
+        ManifestObject {
+            manifest: read_manifest(path),
+            path: path.to_string(),
+        }
+    }
+
+    // A file which already belongs to a mod existed before the install but isn't a game
+    // file, it takes over the flag of its earlier record so the last mod owning it
+    // still removes it on uninstall
+    fn add_mod(&mut self, mut mod_manifest: ModManifest) -> Result<()> {
+        for file in mod_manifest.files.iter_mut() {
+            let earlier = self
+                .manifest
+                .mods
+                .iter()
+                .flat_map(|entry| entry.files.iter())
+                .find(|earlier| earlier.path == file.path);
+            if let Some(earlier) = earlier {
+                file.overwritten = earlier.overwritten;
+            }
+        }
+        self.manifest
+            .mods
+            .retain(|entry| entry.checksum_md5 != mod_manifest.checksum_md5);
+        self.manifest.mods.push(mod_manifest);
+        write_manifest_to_json(Path::new(&self.path), &self.manifest)
+    }
+
+    fn get_mod(&self, md5: &str) -> Option<&ModManifest> {
+        self.manifest
+            .mods
+            .iter()
+            .find(|entry| entry.checksum_md5 == *md5)
+    }
+
+    fn remove_mod(&mut self, md5: &str) -> Result<()> {
+        self.manifest
+            .mods
+            .retain(|entry| entry.checksum_md5 != *md5);
+        write_manifest_to_json(Path::new(&self.path), &self.manifest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn mod_manifest(md5: &str, paths: &[&str]) -> ModManifest {
+        ModManifest {
+            checksum_md5: md5.to_string(),
+            filename: format!("{}.zip", md5),
+            files: paths
+                .iter()
+                .map(|path| ManifestFile {
+                    path: path.to_string(),
+                    size_in_bytes: 3,
+                    checksum_md5: "abc".to_string(),
+                    overwritten: false,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_save_and_load_manifest() {
+        let dir = TempDir::new("manifest_test").unwrap();
+        let path = dir.path().join("manifest.json");
+        let path_str = path.to_str().unwrap();
+
+        let mut manifest = ManifestObject::new(path_str);
+        assert!(manifest.manifest.mods.is_empty());
+        manifest
+            .add_mod(mod_manifest("a", &["content/cars/a/data.acd"]))
+            .unwrap();
+        manifest
+            .add_mod(mod_manifest("b", &["content/tracks/b/map.png"]))
+            .unwrap();
+        assert!(!dir.path().join("manifest.json.tmp").exists());
+
+        let manifest = ManifestObject::new(path_str);
+        assert_eq!(manifest.manifest.mods.len(), 2);
+        let mod_a = manifest.get_mod("a").unwrap();
+        assert_eq!(mod_a.filename, "a.zip");
+        assert_eq!(
+            mod_a.files,
+            mod_manifest("a", &["content/cars/a/data.acd"]).files
+        );
+        assert!(manifest.get_mod("c").is_none());
+    }
+
+    #[test]
+    fn test_remove_mod() {
+        let dir = TempDir::new("manifest_test").unwrap();
+        let path = dir.path().join("manifest.json");
+        let path_str = path.to_str().unwrap();
+
+        let mut manifest = ManifestObject::new(path_str);
+        manifest.add_mod(mod_manifest("a", &["a.ini"])).unwrap();
+        manifest.add_mod(mod_manifest("b", &["b.ini"])).unwrap();
+        manifest.remove_mod("a").unwrap();
+        assert!(manifest.get_mod("a").is_none());
+
+        let manifest = ManifestObject::new(path_str);
+        assert!(manifest.get_mod("a").is_none());
+        assert!(manifest.get_mod("b").is_some());
+    }
+
+    #[test]
+    fn test_write_failure_is_an_error() {
+        let dir = TempDir::new("manifest_test").unwrap();
+        let path = dir.path().join("missing").join("manifest.json");
+
+        let mut manifest = ManifestObject::new(path.to_str().unwrap());
+        let result = manifest.add_mod(mod_manifest("a", &["a.ini"]));
+        assert!(matches!(result, Err(Error::Filesystem(_))));
+    }
+}
@@ -0,0 +1,172 @@
+use std::{future::Future, time::Duration};
+
+use crate::error::{Error, Result};
+
+// cancellation is checked this often while waiting for the next attempt
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// appended to the message of an error the retries gave up on
+pub fn describe_attempts(attempts: u32) -> String {
+    match attempts {
+        0 | 1 => String::new(),
+        _ => format!(" (gave up after {} attempts)", attempts),
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub retryable_statuses: Vec<u16>,
+}
+
+impl RetryPolicy {
+    // only transient failures are worth another attempt, a checksum mismatch or a
+    // 404 will look the same the next time
+    pub fn is_retryable(&self, error: &Error) -> bool {
+        match error {
+            Error::Network(_) => true,
+            Error::Http { status, .. } => self.retryable_statuses.contains(status),
+            _ => false,
+        }
+    }
+
+    // the wait after the given failed attempt (starting at 1), doubled every time
+    pub fn get_backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        std::cmp::min(
+            self.initial_backoff
+                .checked_mul(factor)
+                .unwrap_or(self.max_backoff),
+            self.max_backoff,
+        )
+    }
+
+    // Runs operation until it succeeds, fails with an error which isn't retryable or
+    // runs out of attempts, returns the last result with the number of attempts made
+    pub async fn run<T, F, Fut, C, R>(
+        &self,
+        mut operation: F,
+        is_cancelled: C,
+        mut on_retry: R,
+    ) -> (Result<T>, u32)
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+        C: Fn() -> bool,
+        R: FnMut(&Error, u32, Duration),
+    {
+        let max_attempts = std::cmp::max(self.max_attempts, 1);
+        let mut attempt = 1;
+        loop {
+            let result = operation().await;
+            let error = match &result {
+                Ok(_) => return (result, attempt),
+                Err(error) => error,
+            };
+            if attempt >= max_attempts || !self.is_retryable(error) {
+                return (result, attempt);
+            }
+
+            let backoff = self.get_backoff(attempt);
+            on_retry(error, attempt, backoff);
+            let mut waited = Duration::from_secs(0);
+            while waited < backoff {
+                if is_cancelled() {
+                    return (Err(Error::Cancelled), attempt);
+                }
+                let step = std::cmp::min(CANCEL_POLL_INTERVAL, backoff - waited);
+                tokio::time::sleep(step).await;
+                waited += step;
+            }
+            attempt += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(3),
+            retryable_statuses: vec![503],
+        }
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = policy();
+        assert_eq!(policy.get_backoff(1), Duration::from_millis(1));
+        assert_eq!(policy.get_backoff(2), Duration::from_millis(2));
+        assert_eq!(policy.get_backoff(3), Duration::from_millis(3));
+        assert_eq!(policy.get_backoff(100), Duration::from_millis(3));
+    }
+
+    #[tokio::test]
+    async fn test_retry() {
+        let policy = policy();
+        let unavailable = || Error::Http {
+            status: 503,
+            url: "http://127.0.0.1/mods.json".to_string(),
+        };
+
+        let mut calls = 0;
+        let mut retries = vec![];
+        let (result, attempts) = policy
+            .run(
+                || {
+                    calls += 1;
+                    let result = match calls {
+                        1 => Err(unavailable()),
+                        _ => Ok(calls),
+                    };
+                    async move { result }
+                },
+                || false,
+                |_, attempt, backoff| retries.push((attempt, backoff)),
+            )
+            .await;
+        assert_eq!(result, Ok(2));
+        assert_eq!(attempts, 2);
+        assert_eq!(retries, vec![(1, Duration::from_millis(1))]);
+
+        let (result, attempts) = policy
+            .run(
+                || async { Err::<(), _>(unavailable()) },
+                || false,
+                |_, _, _| {},
+            )
+            .await;
+        assert_eq!(result, Err(unavailable()));
+        assert_eq!(attempts, 3);
+
+        let not_found = Error::Http {
+            status: 404,
+            url: "http://127.0.0.1/mods.json".to_string(),
+        };
+        let (result, attempts) = policy
+            .run(
+                || async { Err::<(), _>(not_found.clone()) },
+                || false,
+                |_, _, _| {},
+            )
+            .await;
+        assert_eq!(result, Err(not_found.clone()));
+        assert_eq!(attempts, 1);
+
+        let (result, attempts) = policy
+            .run(
+                || async { Err::<(), _>(unavailable()) },
+                || true,
+                |_, _, _| {},
+            )
+            .await;
+        assert_eq!(result, Err(Error::Cancelled));
+        assert_eq!(attempts, 1);
+    }
+}
@@ -0,0 +1,276 @@
+use std::{
+    collections::HashMap,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+
+use crate::error::{Error, Result};
+
+const KEYRING_SERVICE: &str = "assetto_sync_client";
+const KEY_SIZE: usize = 32;
+const NONCE_SIZE: usize = 12;
+
+pub trait SecretStore {
+    fn get_name(&self) -> &'static str;
+    fn get_secret(&self, name: &str) -> Result<Option<String>>;
+    fn set_secret(&mut self, name: &str, secret: &str) -> Result<()>;
+    fn delete_secret(&mut self, name: &str) -> Result<()>;
+}
+
+pub fn password_secret_name(profile: &str, login: &str) -> String {
+    format!("password:{}:{}", profile, login)
+}
+
+pub fn session_secret_name(profile: &str, login: &str) -> String {
+    format!("session:{}:{}", profile, login)
+}
+
+// the token doesn't belong to a login, every profile has at most one
+pub fn api_token_secret_name(profile: &str) -> String {
+    format!("api_token:{}", profile)
+}
+
+// Picks the system keyring (Secret Service, Keychain, Credential Manager) when it
+// can be reached, headless machines often have none and get the encrypted file
+pub fn open_secret_store(config_dir: &Path) -> Box<dyn SecretStore> {
+    match KeyringStore::open() {
+        Some(store) => Box::new(store),
+        None => Box::new(EncryptedFileStore::new(
+            &config_dir.join("assetto_sync_client.secrets"),
+            &config_dir.join("assetto_sync_client.key"),
+        )),
+    }
+}
+
+fn keyring_error(error: keyring::Error) -> Error {
+    Error::Config(format!("system keyring: {}", error))
+}
+
+pub struct KeyringStore {}
+
+impl KeyringStore {
+    // whether the keyring works is only known after talking to it
+    pub fn open() -> Option<KeyringStore> {
+        let entry = keyring::Entry::new(KEYRING_SERVICE, "availability_check").ok()?;
+        match entry.get_password() {
+            Ok(_) | Err(keyring::Error::NoEntry) => Some(KeyringStore {}),
+            Err(error) => {
+                eprintln!("System keyring not available: {}", error);
+                None
+            }
+        }
+    }
+
+    fn get_entry(&self, name: &str) -> Result<keyring::Entry> {
+        keyring::Entry::new(KEYRING_SERVICE, name).map_err(keyring_error)
+    }
+}
+
+impl SecretStore for KeyringStore {
+    fn get_name(&self) -> &'static str {
+        "the system keyring"
+    }
+
+    fn get_secret(&self, name: &str) -> Result<Option<String>> {
+        match self.get_entry(name)?.get_password() {
+            Ok(secret) => Ok(Some(secret)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(error) => Err(keyring_error(error)),
+        }
+    }
+
+    fn set_secret(&mut self, name: &str, secret: &str) -> Result<()> {
+        self.get_entry(name)?
+            .set_password(secret)
+            .map_err(keyring_error)
+    }
+
+    fn delete_secret(&mut self, name: &str) -> Result<()> {
+        match self.get_entry(name)?.delete_password() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(error) => Err(keyring_error(error)),
+        }
+    }
+}
+
+// Secrets encrypted with a random key which is kept in a separate file, both only
+// readable by the user. This keeps them out of the config file, but anyone who can
+// read both files can decrypt them
+pub struct EncryptedFileStore {
+    path: PathBuf,
+    key_path: PathBuf,
+}
+
+fn write_private_file(path: &Path, content: &[u8]) -> Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.create(true).write(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let result = options
+        .open(path)
+        .and_then(|mut file| file.write_all(content));
+    if let Err(error) = result {
+        return Err(Error::Filesystem(format!(
+            "cannot write {}: {}",
+            path.display(),
+            error
+        )));
+    }
+    Ok(())
+}
+
+fn random_bytes(size: usize) -> Result<Vec<u8>> {
+    let mut bytes = vec![0u8; size];
+    if let Err(error) = getrandom::getrandom(&mut bytes) {
+        return Err(Error::Config(format!("cannot generate a key: {}", error)));
+    }
+    Ok(bytes)
+}
+
+impl EncryptedFileStore {
+    pub fn new(path: &Path, key_path: &Path) -> EncryptedFileStore {
+        EncryptedFileStore {
+            path: path.to_path_buf(),
+            key_path: key_path.to_path_buf(),
+        }
+    }
+
+    fn get_cipher(&self, create_key: bool) -> Result<Option<ChaCha20Poly1305>> {
+        let key = match std::fs::read(&self.key_path) {
+            Ok(key) => key,
+            Err(_) if create_key => {
+                let key = random_bytes(KEY_SIZE)?;
+                write_private_file(&self.key_path, &key)?;
+                key
+            }
+            Err(_) => return Ok(None),
+        };
+        if key.len() != KEY_SIZE {
+            return Err(Error::Config(format!(
+                "invalid key in {}",
+                self.key_path.display()
+            )));
+        }
+        Ok(Some(ChaCha20Poly1305::new(Key::from_slice(&key))))
+    }
+
+    fn read_secrets(&self) -> Result<HashMap<String, String>> {
+        let content = std::fs::read(&self.path);
+        if content.is_err() {
+            return Ok(HashMap::new());
+        }
+        let content = content.unwrap();
+
+        let cipher = self.get_cipher(false)?;
+        if cipher.is_none() || content.len() < NONCE_SIZE {
+            return Err(Error::Config(format!(
+                "cannot decrypt {}, its key is missing",
+                self.path.display()
+            )));
+        }
+        let (nonce, ciphertext) = content.split_at(NONCE_SIZE);
+        let plaintext = cipher
+            .unwrap()
+            .decrypt(Nonce::from_slice(nonce), ciphertext);
+        if plaintext.is_err() {
+            return Err(Error::Config(format!(
+                "cannot decrypt {}, it doesn't match {}",
+                self.path.display(),
+                self.key_path.display()
+            )));
+        }
+
+        serde_json::from_slice(&plaintext.unwrap())
+            .map_err(|error| Error::Config(format!("invalid {}: {}", self.path.display(), error)))
+    }
+
+    fn write_secrets(&self, secrets: &HashMap<String, String>) -> Result<()> {
+        let cipher = self.get_cipher(true)?.unwrap();
+        let plaintext = serde_json::to_vec(secrets).unwrap();
+        let nonce = random_bytes(NONCE_SIZE)?;
+        let ciphertext = cipher.encrypt(Nonce::from_slice(&nonce), plaintext.as_slice());
+        if ciphertext.is_err() {
+            return Err(Error::Config(format!(
+                "cannot encrypt {}",
+                self.path.display()
+            )));
+        }
+
+        let mut content = nonce;
+        content.extend(ciphertext.unwrap());
+        write_private_file(&self.path, &content)
+    }
+}
+
+impl SecretStore for EncryptedFileStore {
+    fn get_name(&self) -> &'static str {
+        "the encrypted secrets file"
+    }
+
+    fn get_secret(&self, name: &str) -> Result<Option<String>> {
+        Ok(self.read_secrets()?.remove(name))
+    }
+
+    fn set_secret(&mut self, name: &str, secret: &str) -> Result<()> {
+        let mut secrets = self.read_secrets()?;
+        secrets.insert(name.to_string(), secret.to_string());
+        self.write_secrets(&secrets)
+    }
+
+    fn delete_secret(&mut self, name: &str) -> Result<()> {
+        let mut secrets = self.read_secrets()?;
+        if secrets.remove(name).is_none() {
+            return Ok(());
+        }
+        self.write_secrets(&secrets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_encrypted_file_store() {
+        let dir = TempDir::new("secret_store_test").unwrap();
+        let path = dir.path().join("secrets");
+        let key_path = dir.path().join("key");
+        let mut store = EncryptedFileStore::new(&path, &key_path);
+
+        assert_eq!(store.get_secret("password:user"), Ok(None));
+        store.set_secret("password:user", "hunter2").unwrap();
+        store.set_secret("password:other", "letmein").unwrap();
+        assert_eq!(
+            store.get_secret("password:user"),
+            Ok(Some("hunter2".to_string()))
+        );
+
+        let content = std::fs::read(&path).unwrap();
+        assert!(!content.windows(7).any(|window| window == b"hunter2"));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&key_path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        store.delete_secret("password:user").unwrap();
+        assert_eq!(store.get_secret("password:user"), Ok(None));
+        assert_eq!(
+            store.get_secret("password:other"),
+            Ok(Some("letmein".to_string()))
+        );
+
+        // secrets encrypted with another key can't be read
+        std::fs::write(&key_path, vec![0u8; KEY_SIZE]).unwrap();
+        assert!(store.get_secret("password:other").is_err());
+    }
+}
@@ -0,0 +1,564 @@
+use std::{
+    future::Future,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use reqwest::{cookie::Jar, header::LOCATION, Client, ClientBuilder, Response, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::{Authenticator, PasswordAuthenticator, TokenAuthenticator};
+use crate::endpoints::Endpoints;
+use crate::error::{Error, Result};
+use crate::retry::RetryPolicy;
+use crate::JsonModTemplate;
+
+// cookies without an expiry date only live as long as the browser session,
+// they are kept for at most this long
+const SESSION_COOKIE_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+#[derive(Clone)]
+pub struct LoginData {
+    pub login: String,
+    pub password: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StoredCookie {
+    pub name: String,
+    pub value: String,
+    // seconds since the unix epoch
+    pub expires: u64,
+    // the scope set by the server, sessions stored by older versions have none
+    #[serde(default = "default_cookie_path")]
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub domain: Option<String>,
+    #[serde(default)]
+    pub secure: bool,
+    #[serde(default)]
+    pub http_only: bool,
+}
+
+fn default_cookie_path() -> String {
+    "/".to_string()
+}
+
+// a cookie without a Path belongs to the directory of the URL which set it (RFC 6265)
+fn get_default_cookie_path(url: &Url) -> String {
+    let path = url.path();
+    match path.rfind('/') {
+        Some(0) | None => default_cookie_path(),
+        Some(index) => path[..index].to_string(),
+    }
+}
+
+impl StoredCookie {
+    // the cookie as the server sent it, except for the expiry which is checked by
+    // StoredSession
+    fn to_set_cookie(&self) -> String {
+        let mut set_cookie = format!("{}={}; Path={}", self.name, self.value, self.path);
+        if let Some(domain) = &self.domain {
+            set_cookie += &format!("; Domain={}", domain);
+        }
+        if self.secure {
+            set_cookie += "; Secure";
+        }
+        if self.http_only {
+            set_cookie += "; HttpOnly";
+        }
+        set_cookie
+    }
+}
+
+// The cookies of a login, kept between runs so the password isn't sent every time
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct StoredSession {
+    pub server_url: String,
+    pub cookies: Vec<StoredCookie>,
+}
+
+fn to_unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+impl StoredSession {
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        let now = to_unix_time(now);
+        self.cookies.is_empty() || self.cookies.iter().any(|cookie| cookie.expires <= now)
+    }
+}
+
+// redirects aren't followed, so a redirect to the login page can be told apart
+// from the requested file
+pub fn client_builder() -> ClientBuilder {
+    reqwest::Client::builder().redirect(reqwest::redirect::Policy::custom(|attempt| attempt.stop()))
+}
+
+pub fn build_client(cookies: Arc<Jar>) -> Result<Client> {
+    let client = client_builder().cookie_provider(cookies).build()?;
+    Ok(client)
+}
+
+pub async fn login_client(
+    endpoints: &Endpoints,
+    login_data: &LoginData,
+) -> Result<(Client, StoredSession)> {
+    let client = build_client(Arc::new(Jar::default()))?;
+
+    let response = client
+        .post(endpoints.login())
+        .form(&[
+            ("login", &login_data.login),
+            ("password", &login_data.password),
+        ])
+        .send()
+        .await;
+
+    if let Err(error) = response {
+        return Err(Error::from(error));
+    }
+
+    let response = response.unwrap();
+    let has_user_name_cookie = response.cookies().any(|c| c.name() == "user_name");
+    if !has_user_name_cookie {
+        return Err(Error::Auth("Login failed (wrong password?)".to_string()));
+    }
+
+    let now = SystemTime::now();
+    let cookies = response
+        .cookies()
+        .map(|cookie| {
+            let expires = match (cookie.max_age(), cookie.expires()) {
+                (Some(max_age), _) => now + max_age,
+                (None, Some(expires)) => expires,
+                (None, None) => now + SESSION_COOKIE_MAX_AGE,
+            };
+            StoredCookie {
+                name: cookie.name().to_string(),
+                value: cookie.value().to_string(),
+                expires: to_unix_time(expires),
+                path: cookie
+                    .path()
+                    .map(|path| path.to_string())
+                    .unwrap_or_else(|| get_default_cookie_path(&endpoints.login())),
+                domain: cookie.domain().map(|domain| domain.to_string()),
+                secure: cookie.secure(),
+                http_only: cookie.http_only(),
+            }
+        })
+        .collect();
+    let stored_session = StoredSession {
+        server_url: endpoints.base_url().to_string(),
+        cookies,
+    };
+    Ok((client, stored_session))
+}
+
+fn password_session(
+    client: Client,
+    stored_session: StoredSession,
+    endpoints: &Endpoints,
+    login_data: &LoginData,
+) -> Session {
+    let authenticator = PasswordAuthenticator::new(
+        client,
+        stored_session,
+        endpoints.clone(),
+        login_data.clone(),
+    );
+    Session::new(Arc::new(authenticator), endpoints.clone())
+}
+
+pub async fn login(endpoints: &Endpoints, login_data: &LoginData) -> Result<Session> {
+    let (client, stored_session) = login_client(endpoints, login_data).await?;
+    Ok(password_session(
+        client,
+        stored_session,
+        endpoints,
+        login_data,
+    ))
+}
+
+fn restore_cookies(stored_session: &StoredSession, url: &Url) -> Arc<Jar> {
+    let cookies = Arc::new(Jar::default());
+    for cookie in stored_session.cookies.iter() {
+        cookies.add_cookie_str(&cookie.to_set_cookie(), url);
+    }
+    cookies
+}
+
+// Reuses the cookies of an earlier login, the server is asked for mods.json to
+// find out whether it still accepts them. login_data is only used when the
+// session expires later on
+pub async fn restore_session(
+    endpoints: &Endpoints,
+    login_data: &LoginData,
+    stored_session: &StoredSession,
+) -> Result<Session> {
+    if stored_session.server_url != endpoints.base_url().as_str()
+        || stored_session.is_expired(SystemTime::now())
+    {
+        return Err(Error::SessionExpired);
+    }
+
+    let client = build_client(restore_cookies(stored_session, endpoints.base_url()))?;
+    let response = client.get(endpoints.mods_json()).send().await?;
+    check_response(response)?;
+
+    Ok(password_session(
+        client,
+        stored_session.clone(),
+        endpoints,
+        login_data,
+    ))
+}
+
+// nothing is sent until the first request, a rejected token shows up there
+pub fn token_session(endpoints: &Endpoints, token: &str) -> Result<Session> {
+    let authenticator = TokenAuthenticator::new(token)?;
+    Ok(Session::new(Arc::new(authenticator), endpoints.clone()))
+}
+
+// Any response which isn't 2xx becomes an error, the server answers requests
+// of a user whose session expired with a redirect to the login page
+pub fn check_response(response: Response) -> Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let redirects_to_login = status.is_redirection()
+        && response
+            .headers()
+            .get(LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .map(|location| location.contains("login"))
+            .unwrap_or(false);
+    if redirects_to_login || status == StatusCode::UNAUTHORIZED {
+        return Err(Error::SessionExpired);
+    }
+
+    Err(Error::Http {
+        status: status.as_u16(),
+        url: response.url().to_string(),
+    })
+}
+
+// An authorized client for the server, clones share the authenticator so a login
+// done by one of them is used by all
+#[derive(Clone)]
+pub struct Session {
+    authenticator: Arc<dyn Authenticator>,
+    endpoints: Endpoints,
+    // counts the logins, held while one runs so parallel downloads which find the
+    // session expired log in once
+    reauthentications: Arc<tokio::sync::Mutex<u64>>,
+}
+
+impl Session {
+    pub fn new(authenticator: Arc<dyn Authenticator>, endpoints: Endpoints) -> Session {
+        Session {
+            authenticator,
+            endpoints,
+            reauthentications: Arc::new(tokio::sync::Mutex::new(0)),
+        }
+    }
+
+    pub fn get_client(&self) -> Client {
+        self.authenticator.get_client()
+    }
+
+    pub fn get_endpoints(&self) -> &Endpoints {
+        &self.endpoints
+    }
+
+    // changes when the session had to log in again
+    pub fn get_stored_session(&self) -> Option<StoredSession> {
+        self.authenticator.get_stored_session()
+    }
+
+    // Logs in again unless another request did since seen_reauthentications was read,
+    // then its client is used. A failed login isn't repeated by the requests waiting
+    // for it, they fail with the expired session
+    async fn reauthenticate(&self, seen_reauthentications: u64) -> Result<()> {
+        let mut reauthentications = self.reauthentications.lock().await;
+        if *reauthentications != seen_reauthentications {
+            return Ok(());
+        }
+        *reauthentications += 1;
+        self.authenticator.reauthenticate().await
+    }
+
+    // Sends a request with the current client, when the session expired it logs in
+    // again and repeats the request once
+    pub async fn send<T, F, Fut>(&self, request: F) -> Result<T>
+    where
+        F: Fn(Client) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        // a login which is running is waited for, its client is the one to use
+        let (seen_reauthentications, client) = {
+            let reauthentications = self.reauthentications.lock().await;
+            (*reauthentications, self.get_client())
+        };
+        match request(client).await {
+            Err(Error::SessionExpired) => {}
+            result => return result,
+        }
+        self.reauthenticate(seen_reauthentications).await?;
+        request(self.get_client()).await
+    }
+}
+
+async fn fetch_mod_list(client: Client, endpoints: &Endpoints) -> Result<Vec<JsonModTemplate>> {
+    let response = client.get(endpoints.mods_json()).send().await?;
+    let response = check_response(response)?;
+    let mod_list: Vec<JsonModTemplate> = response.json().await?;
+    Ok(mod_list)
+}
+
+// returns the number of attempts next to the result, so giving up can be reported
+pub async fn get_mod_list(
+    session: &Session,
+    retry_policy: &RetryPolicy,
+) -> (Result<Vec<JsonModTemplate>>, u32) {
+    retry_policy
+        .run(
+            || session.send(|client| fetch_mod_list(client, session.get_endpoints())),
+            || false,
+            |error, attempt, backoff| {
+                eprintln!(
+                    "Receiving mods failed ({}), retrying in {}ms (attempt {}/{})",
+                    error,
+                    backoff.as_millis(),
+                    attempt,
+                    retry_policy.max_attempts
+                )
+            },
+        )
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::AuthFuture;
+    use crate::test_utils::{fixture, retry_policy, serve, task};
+    use std::{collections::HashMap, sync::Mutex};
+
+    // counts the logins of the authenticator it wraps
+    struct CountingAuthenticator {
+        authenticator: PasswordAuthenticator,
+        logins: Mutex<u32>,
+    }
+
+    impl Authenticator for CountingAuthenticator {
+        fn get_client(&self) -> Client {
+            self.authenticator.get_client()
+        }
+
+        fn get_stored_session(&self) -> Option<StoredSession> {
+            self.authenticator.get_stored_session()
+        }
+
+        fn reauthenticate(&self) -> AuthFuture<'_> {
+            *self.logins.lock().unwrap() += 1;
+            self.authenticator.reauthenticate()
+        }
+    }
+
+    fn stored_session(server_url: &str, expires: u64) -> StoredSession {
+        StoredSession {
+            server_url: server_url.to_string(),
+            cookies: vec![StoredCookie {
+                name: "user_name".to_string(),
+                value: "test".to_string(),
+                expires,
+                path: default_cookie_path(),
+                domain: None,
+                secure: false,
+                http_only: false,
+            }],
+        }
+    }
+
+    fn login_data() -> LoginData {
+        LoginData {
+            login: "test".to_string(),
+            password: "test".to_string(),
+        }
+    }
+
+    async fn download(session: &Session, md5_hash: &str) -> Result<Vec<u8>> {
+        let link = session.get_endpoints().mod_download(md5_hash);
+        session
+            .send(|client| {
+                let request = client.get(link.clone());
+                async move {
+                    let response = check_response(request.send().await?)?;
+                    Ok(response.bytes().await?.to_vec())
+                }
+            })
+            .await
+    }
+
+    #[test]
+    fn test_stored_session_expiry() {
+        let now = UNIX_EPOCH + Duration::from_secs(1000);
+        assert!(!stored_session("http://127.0.0.1:8080/", 1001).is_expired(now));
+        assert!(stored_session("http://127.0.0.1:8080/", 1000).is_expired(now));
+        assert!(StoredSession::default().is_expired(now));
+    }
+
+    #[test]
+    fn test_restored_cookies_keep_their_scope() {
+        use reqwest::cookie::CookieStore;
+
+        let url = Url::parse("http://sync.example.com/").unwrap();
+        let mut session = stored_session(url.as_str(), 1);
+        session.cookies[0].path = "/private".to_string();
+        let cookies = restore_cookies(&session, &url);
+        assert!(cookies.cookies(&url.join("mods.json").unwrap()).is_none());
+        assert!(cookies.cookies(&url.join("private/mod").unwrap()).is_some());
+
+        session.cookies[0].path = default_cookie_path();
+        session.cookies[0].domain = Some("example.com".to_string());
+        let cookies = restore_cookies(&session, &url);
+        let other_host = Url::parse("http://cdn.example.com/mod").unwrap();
+        assert!(cookies.cookies(&other_host).is_some());
+
+        // sessions stored by older versions are sent to the whole server
+        let session: StoredSession = serde_json::from_str(
+            r#"{"server_url": "http://sync.example.com/",
+                "cookies": [{"name": "user_name", "value": "test", "expires": 1}]}"#,
+        )
+        .unwrap();
+        let cookies = restore_cookies(&session, &url);
+        assert!(cookies.cookies(&url.join("mods.json").unwrap()).is_some());
+
+        let login_url = Url::parse("http://sync.example.com/app/login").unwrap();
+        assert_eq!(get_default_cookie_path(&login_url), "/app");
+        assert_eq!(get_default_cookie_path(&url.join("login").unwrap()), "/");
+    }
+
+    #[tokio::test]
+    async fn test_restore_session_rejects_unusable_sessions() {
+        let endpoints = Endpoints::new("http://127.0.0.1:8080").unwrap();
+        let expires = to_unix_time(SystemTime::now() + SESSION_COOKIE_MAX_AGE);
+
+        // neither is sent to the server
+        let expired = stored_session("http://127.0.0.1:8080/", 1);
+        let result = restore_session(&endpoints, &login_data(), &expired).await;
+        assert!(matches!(result, Err(Error::SessionExpired)));
+        let other_server = stored_session("http://example.com/", expires);
+        let result = restore_session(&endpoints, &login_data(), &other_server).await;
+        assert!(matches!(result, Err(Error::SessionExpired)));
+    }
+
+    #[tokio::test]
+    async fn test_expired_session_logs_in_again() {
+        let valid = fixture("valid.zip");
+        let installed = task("installed.zip", &valid);
+        let mut responses = HashMap::new();
+        responses.insert(installed.checksum_md5.clone(), (200, valid.clone()));
+        let server_url = serve(responses, true).await;
+        let endpoints = Endpoints::new(&server_url).unwrap();
+
+        // the client has no session cookie yet
+        let authenticator = PasswordAuthenticator::new(
+            build_client(Arc::new(Jar::default())).unwrap(),
+            StoredSession::default(),
+            endpoints.clone(),
+            login_data(),
+        );
+        let session = Session::new(Arc::new(authenticator), endpoints);
+        assert_eq!(
+            download(&session, &installed.checksum_md5).await.unwrap(),
+            valid
+        );
+        assert_eq!(session.get_stored_session().unwrap().cookies.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_parallel_requests_log_in_once() {
+        let valid = fixture("valid.zip");
+        let installed = task("installed.zip", &valid);
+        let mut responses = HashMap::new();
+        responses.insert(installed.checksum_md5.clone(), (200, valid.clone()));
+        let server_url = serve(responses, true).await;
+        let endpoints = Endpoints::new(&server_url).unwrap();
+
+        let authenticator = Arc::new(CountingAuthenticator {
+            authenticator: PasswordAuthenticator::new(
+                build_client(Arc::new(Jar::default())).unwrap(),
+                StoredSession::default(),
+                endpoints.clone(),
+                login_data(),
+            ),
+            logins: Mutex::new(0),
+        });
+        let session = Session::new(authenticator.clone(), endpoints);
+        let md5 = &installed.checksum_md5;
+        let (first, second, third) = tokio::join!(
+            download(&session, md5),
+            download(&session, md5),
+            download(&session, md5)
+        );
+        for result in [first, second, third] {
+            assert_eq!(result.unwrap(), valid);
+        }
+        assert_eq!(*authenticator.logins.lock().unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_stored_session_is_reused() {
+        let server_url = serve(HashMap::new(), true).await;
+        let endpoints = Endpoints::new(&server_url).unwrap();
+        let stored_session = login(&endpoints, &login_data())
+            .await
+            .unwrap()
+            .get_stored_session()
+            .unwrap();
+        assert_eq!(stored_session.cookies.len(), 1);
+        assert_eq!(stored_session.cookies[0].path, "/");
+
+        let session = restore_session(&endpoints, &login_data(), &stored_session)
+            .await
+            .unwrap();
+        let (mod_list, _) = get_mod_list(&session, &retry_policy()).await;
+        assert_eq!(mod_list.unwrap().len(), 0);
+
+        // a cookie the server doesn't know is rejected
+        let mut rejected_session = stored_session.clone();
+        rejected_session.cookies[0].name = "other".to_string();
+        let result = restore_session(&endpoints, &login_data(), &rejected_session).await;
+        assert!(matches!(result, Err(Error::SessionExpired)));
+    }
+
+    #[tokio::test]
+    async fn test_api_token_is_sent() {
+        let valid = fixture("valid.zip");
+        let installed = task("installed.zip", &valid);
+        let mut responses = HashMap::new();
+        responses.insert(installed.checksum_md5.clone(), (200, valid.clone()));
+        let server_url = serve(responses, true).await;
+        let endpoints = Endpoints::new(&server_url).unwrap();
+
+        let session = token_session(&endpoints, "secret").unwrap();
+        let (mod_list, _) = get_mod_list(&session, &retry_policy()).await;
+        assert!(mod_list.is_ok());
+        assert_eq!(
+            download(&session, &installed.checksum_md5).await.unwrap(),
+            valid
+        );
+        assert_eq!(session.get_stored_session(), None);
+
+        // a rejected token isn't retried with a login
+        let session = token_session(&endpoints, "wrong").unwrap();
+        let (mod_list, _) = get_mod_list(&session, &retry_policy()).await;
+        assert!(matches!(mod_list, Err(Error::Auth(_))));
+    }
+}
@@ -0,0 +1,122 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::retry::RetryPolicy;
+use crate::JsonModTemplate;
+
+pub fn fixture_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join(name)
+}
+
+pub fn fixture(name: &str) -> Vec<u8> {
+    std::fs::read(fixture_path(name)).unwrap()
+}
+
+// a mod list entry matching the given archive content
+pub fn task(filename: &str, content: &[u8]) -> JsonModTemplate {
+    JsonModTemplate {
+        checksum_md5: format!("{:x}", md5::compute(content)),
+        filename: filename.to_string(),
+        size_in_bytes: content.len() as u64,
+    }
+}
+
+pub fn retry_policy() -> RetryPolicy {
+    RetryPolicy {
+        max_attempts: 2,
+        initial_backoff: Duration::from_millis(1),
+        max_backoff: Duration::from_millis(1),
+        retryable_statuses: vec![503],
+    }
+}
+
+pub async fn read_request(stream: &mut TcpStream) -> String {
+    let mut request = vec![];
+    let mut buffer = [0u8; 1024];
+    loop {
+        if let Some(end) = request.windows(4).position(|window| window == b"\r\n\r\n") {
+            let head = String::from_utf8_lossy(&request[..end]).to_lowercase();
+            let content_length = head
+                .lines()
+                .find_map(|line| line.strip_prefix("content-length:"))
+                .and_then(|length| length.trim().parse::<usize>().ok())
+                .unwrap_or(0);
+            if request.len() >= end + 4 + content_length {
+                break;
+            }
+        }
+        match stream.read(&mut buffer).await {
+            Ok(0) | Err(_) => break,
+            Ok(read) => request.extend_from_slice(&buffer[..read]),
+        }
+    }
+    String::from_utf8_lossy(&request).to_string()
+}
+
+// Stands in for the sync server, a download is answered with the response
+// registered for its hash and 404 when there is none, a Range request gets the
+// rest of it with 206. With require_login, requests without the cookie set by
+// POST /login or the API token "secret" are redirected to the login page
+pub async fn serve(responses: HashMap<String, (u16, Vec<u8>)>, require_login: bool) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let request = read_request(&mut stream).await;
+            let request_line = request.lines().next().unwrap_or("").to_string();
+            let logged_in = request.to_lowercase().contains("cookie: user_name=")
+                || request.contains("Bearer secret\r\n");
+
+            let mut headers = String::new();
+            let (mut status, mut body) = if request_line.starts_with("POST /login") {
+                headers += "Set-Cookie: user_name=test; Path=/\r\n";
+                (200, vec![])
+            } else if request_line.starts_with("GET /login") {
+                (200, b"<html>login page</html>".to_vec())
+            } else if require_login && !logged_in {
+                headers += "Location: /login\r\n";
+                (302, vec![])
+            } else if request_line.starts_with("GET /mods.json") {
+                (200, b"[]".to_vec())
+            } else {
+                responses
+                    .iter()
+                    .find(|(hash, _)| request_line.contains(&format!("hash={}", hash)))
+                    .map(|(_, response)| response.clone())
+                    .unwrap_or((404, b"not found".to_vec()))
+            };
+            let range_start = request
+                .to_lowercase()
+                .lines()
+                .find_map(|line| line.strip_prefix("range: bytes="))
+                .and_then(|range| range.trim_end_matches('-').parse::<usize>().ok());
+            if let (200, Some(start)) = (status, range_start) {
+                if start < body.len() {
+                    status = 206;
+                    body = body[start..].to_vec();
+                }
+            }
+            let header = format!(
+                "HTTP/1.1 {} Test\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n",
+                status,
+                headers,
+                body.len()
+            );
+            let _ = stream.write_all(header.as_bytes()).await;
+            let _ = stream.write_all(&body).await;
+        }
+    });
+    format!("http://{}", address)
+}
@@ -0,0 +1,192 @@
+use std::path::Path;
+
+use crate::config::{ConfigObject, ConfigTrait};
+use crate::error::{Error, Result};
+use crate::manifest::{ManifestObject, ManifestTrait};
+
+pub struct UninstallResult {
+    pub filename: String,
+    pub removed_files: Vec<String>,
+    pub kept_files: Vec<String>,
+}
+
+// Top level directories like content/cars are shared by every mod, only directories
+// below them are removed once they become empty
+const KEPT_DIRECTORY_DEPTH: usize = 2;
+
+pub fn remove_empty_parent_dirs(assetto_path: &Path, relative_path: &Path) {
+    let mut dir = relative_path.parent();
+    while let Some(relative_dir) = dir {
+        if relative_dir.components().count() <= KEPT_DIRECTORY_DEPTH {
+            break;
+        }
+        // remove_dir fails on non-empty directories, which ends the walk
+        if std::fs::remove_dir(assetto_path.join(relative_dir)).is_err() {
+            break;
+        }
+        dir = relative_dir.parent();
+    }
+}
+
+pub fn uninstall_mod(
+    assetto_path: &Path,
+    md5: &String,
+    config: &mut ConfigObject,
+    manifest: &mut ManifestObject,
+) -> Result<UninstallResult> {
+    let mod_manifest = manifest.get_mod(md5);
+    if mod_manifest.is_none() {
+        return Err(Error::Config(format!(
+            "Mod {} has no recorded file list, it cannot be uninstalled",
+            md5
+        )));
+    }
+    let mod_manifest = mod_manifest.unwrap().clone();
+
+    let mut result = UninstallResult {
+        filename: mod_manifest.filename.clone(),
+        removed_files: vec![],
+        kept_files: vec![],
+    };
+    for file in mod_manifest.files.iter() {
+        // files which replaced existing game files or are also owned by another mod stay
+        let is_shared = manifest.manifest.mods.iter().any(|other| {
+            other.checksum_md5 != mod_manifest.checksum_md5
+                && other
+                    .files
+                    .iter()
+                    .any(|other_file| other_file.path == file.path)
+        });
+        if file.overwritten || is_shared {
+            result.kept_files.push(file.path.clone());
+            continue;
+        }
+
+        let relative_path = Path::new(&file.path);
+        let path = assetto_path.join(relative_path);
+        if let Err(error) = std::fs::remove_file(&path) {
+            if error.kind() != std::io::ErrorKind::NotFound {
+                return Err(Error::Filesystem(format!(
+                    "Mod {}, cannot remove {}: {}",
+                    mod_manifest.filename,
+                    path.display(),
+                    error
+                )));
+            }
+        }
+        remove_empty_parent_dirs(assetto_path, relative_path);
+        result.removed_files.push(file.path.clone());
+    }
+
+    manifest.remove_mod(md5)?;
+    config.remove_installed_mod(md5);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::{ManifestFile, ModManifest};
+    use crate::secret_store::EncryptedFileStore;
+    use tempdir::TempDir;
+
+    // writes the files to the game directory, overwritten tells whether they existed
+    fn install(
+        assetto_path: &Path,
+        manifest: &mut ManifestObject,
+        md5: &str,
+        paths: &[&str],
+    ) -> Vec<bool> {
+        let files = paths
+            .iter()
+            .map(|path| {
+                let target_path = assetto_path.join(path);
+                let overwritten = target_path.exists();
+                std::fs::create_dir_all(target_path.parent().unwrap()).unwrap();
+                std::fs::write(&target_path, md5).unwrap();
+                ManifestFile {
+                    path: path.to_string(),
+                    size_in_bytes: md5.len() as u64,
+                    checksum_md5: String::default(),
+                    overwritten,
+                }
+            })
+            .collect();
+        manifest
+            .add_mod(ModManifest {
+                checksum_md5: md5.to_string(),
+                filename: format!("{}.zip", md5),
+                files,
+            })
+            .unwrap();
+        let files = &manifest.get_mod(md5).unwrap().files;
+        files.iter().map(|file| file.overwritten).collect()
+    }
+
+    #[test]
+    fn test_uninstall_mod() {
+        let dir = TempDir::new("uninstall_test").unwrap();
+        let assetto_path = dir.path().join("assetto");
+        std::fs::create_dir_all(assetto_path.join("system/cfg")).unwrap();
+        std::fs::write(assetto_path.join("system/cfg/video.ini"), "game").unwrap();
+
+        let secrets = EncryptedFileStore::new(&dir.path().join("secrets"), &dir.path().join("key"));
+        let mut config = ConfigObject::open(
+            dir.path().join("config.json").to_str().unwrap(),
+            Box::new(secrets),
+            &dir.path().join("backups"),
+        );
+        let mut manifest = ManifestObject::new(dir.path().join("manifest.json").to_str().unwrap());
+        let (a, b) = ("a".to_string(), "b".to_string());
+        config.add_installed_mod(&a);
+        config.add_installed_mod(&b);
+
+        let overwritten = install(
+            &assetto_path,
+            &mut manifest,
+            &a,
+            &[
+                "content/cars/a/data.acd",
+                "content/cars/a/ui/ui_car.json",
+                "system/cfg/video.ini",
+            ],
+        );
+        assert_eq!(overwritten, vec![false, false, true]);
+        // the file of the first mod isn't a game file to the second one
+        let overwritten = install(
+            &assetto_path,
+            &mut manifest,
+            &b,
+            &["content/cars/a/data.acd", "content/cars/b/data.acd"],
+        );
+        assert_eq!(overwritten, vec![false, false]);
+
+        // replaced game files and files of other mods stay
+        let result = uninstall_mod(&assetto_path, &a, &mut config, &mut manifest).unwrap();
+        assert_eq!(result.removed_files, vec!["content/cars/a/ui/ui_car.json"]);
+        assert_eq!(
+            result.kept_files,
+            vec!["content/cars/a/data.acd", "system/cfg/video.ini"]
+        );
+        assert!(!assetto_path.join("content/cars/a/ui").exists());
+        assert!(assetto_path.join("content/cars/a/data.acd").exists());
+        assert!(!config.is_mod_installed(&a));
+        assert!(manifest.get_mod(&a).is_none());
+
+        // the last mod owning a file removes it, empty directories below content/cars go
+        let result = uninstall_mod(&assetto_path, &b, &mut config, &mut manifest).unwrap();
+        assert_eq!(
+            result.removed_files,
+            vec!["content/cars/a/data.acd", "content/cars/b/data.acd"]
+        );
+        assert!(result.kept_files.is_empty());
+        assert!(!assetto_path.join("content/cars/a").exists());
+        assert!(!assetto_path.join("content/cars/b").exists());
+        assert!(assetto_path.join("content/cars").exists());
+        assert!(assetto_path.join("system/cfg/video.ini").exists());
+        assert!(!config.is_mod_installed(&b));
+
+        let result = uninstall_mod(&assetto_path, &a, &mut config, &mut manifest);
+        assert!(matches!(result, Err(Error::Config(_))));
+    }
+}
@@ -0,0 +1,57 @@
+use std::{
+    path::Path,
+    process::{Command, Output},
+};
+
+use tempdir::TempDir;
+
+// runs the client in command line mode with its config and cache inside dir
+fn run_client(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_assettosync-client"))
+        .args(args)
+        .env("HOME", dir)
+        .env("XDG_CONFIG_HOME", dir.join("config"))
+        .env("XDG_CACHE_HOME", dir.join("cache"))
+        .env_remove("ASSETTO_SYNC_PROFILE")
+        .env_remove("ASSETTO_SYNC_SERVER_URL")
+        .env_remove("ASSETTO_SYNC_API_TOKEN")
+        .output()
+        .unwrap()
+}
+
+// the config directory only follows XDG_CONFIG_HOME on Linux, elsewhere the test
+// would use the real config
+#[cfg(target_os = "linux")]
+#[test]
+fn test_json_output_is_only_json() {
+    let dir = TempDir::new("cli_test").unwrap();
+    let config_dir = dir.path().join("config");
+    std::fs::create_dir_all(&config_dir).unwrap();
+    std::fs::write(
+        config_dir.join("assetto_sync_client.json"),
+        r#"{"version": 1, "retry_attempts": 2, "retry_backoff_ms": 1, "retry_max_backoff_ms": 1}"#,
+    )
+    .unwrap();
+    std::fs::write(config_dir.join("assetto_sync_manifest.default.json"), "{").unwrap();
+
+    // the unreadable manifest and the retried request are reported on stderr
+    let output = run_client(
+        dir.path(),
+        &[
+            "--json",
+            "--server-url",
+            "http://127.0.0.1:1",
+            "--api-token",
+            "secret",
+            "list",
+        ],
+    );
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let error: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert!(error["error"].is_string());
+    assert_eq!(
+        error["exit_code"].as_i64(),
+        output.status.code().map(i64::from)
+    );
+    assert!(!output.stderr.is_empty());
+}